@@ -63,6 +63,9 @@ pub const LUA_MINSTACK: c_int = 20;
 pub const LUA_RIDX_MAINTHREAD: c_int = 1;
 pub const LUA_RIDX_GLOBALS: c_int = 2;
 
+pub const LUA_NOREF: c_int = -2;
+pub const LUA_REFNIL: c_int = -1;
+
 pub type lua_Number = libc::c_double;
 pub type lua_Integer = libc::ptrdiff_t;
 pub type lua_Unsigned = libc::c_ulong;
@@ -118,7 +121,9 @@ pub struct lua_Debug {
     pub nparams: libc::c_uchar,
     pub isvararg: libc::c_char,
     pub istailcall: libc::c_char,
-    pub short_src: [libc::c_char; 60], // i_ci: *CallInfo
+    pub short_src: [libc::c_char; 60],
+    // active function, private to Lua
+    pub i_ci: *mut libc::c_void,
 }
 
 extern "C" {
@@ -136,7 +141,6 @@ extern "C" {
     pub fn lua_pushvalue(L: *mut lua_State, idx: c_int);
     pub fn lua_rotate(L: *mut lua_State, idx: c_int, n: c_int);
 
-    pub fn lua_replace(L: *mut lua_State, idx: c_int);
     pub fn lua_copy(L: *mut lua_State, fromidx: c_int, toidx: c_int);
     pub fn lua_checkstack(L: *mut lua_State, sz: c_int) -> c_int;
 
@@ -146,6 +150,7 @@ extern "C" {
     pub fn lua_isstring(L: *mut lua_State, idx: c_int) -> c_int;
     pub fn lua_iscfunction(L: *mut lua_State, idx: c_int) -> c_int;
     pub fn lua_isuserdata(L: *mut lua_State, idx: c_int) -> c_int;
+    pub fn lua_isinteger(L: *mut lua_State, idx: c_int) -> c_int;
     pub fn lua_type(L: *mut lua_State, idx: c_int) -> c_int;
     pub fn lua_typename(L: *mut lua_State, tp: c_int) -> *const libc::c_char;
 
@@ -186,20 +191,24 @@ extern "C" {
     pub fn lua_gettable(L: *mut lua_State, idx: c_int);
     pub fn lua_getfield(L: *mut lua_State, idx: c_int, k: *const libc::c_char);
     pub fn lua_rawget(L: *mut lua_State, idx: c_int);
-    pub fn lua_rawgeti(L: *mut lua_State, idx: c_int, n: c_int);
+    // `n` is a `lua_Integer` as in the Lua 5.3 prototypes of lua_rawgeti and lua_rawseti;
+    // up to td_clua 0.1 it was declared as a `c_int`, callers passing one need a cast
+    pub fn lua_rawgeti(L: *mut lua_State, idx: c_int, n: lua_Integer);
     pub fn lua_rawgetp(L: *mut lua_State, idx: c_int, p: *const libc::c_char);
     pub fn lua_createtable(L: *mut lua_State, narr: c_int, nrec: c_int);
     pub fn lua_newuserdata(L: *mut lua_State, sz: libc::size_t) -> *mut libc::c_void;
     pub fn lua_getmetatable(L: *mut lua_State, objindex: c_int) -> c_int;
     pub fn lua_getfenv(L: *mut lua_State, idx: c_int);
+    pub fn lua_getuservalue(L: *mut lua_State, idx: c_int) -> c_int;
 
     pub fn lua_setglobal(L: *mut lua_State, var: *const libc::c_char);
     pub fn lua_settable(L: *mut lua_State, idx: c_int);
     pub fn lua_setfield(L: *mut lua_State, idx: c_int, k: *const libc::c_char);
     pub fn lua_rawset(L: *mut lua_State, idx: c_int);
-    pub fn lua_rawseti(L: *mut lua_State, idx: c_int, n: c_int);
+    pub fn lua_rawseti(L: *mut lua_State, idx: c_int, n: lua_Integer);
     pub fn lua_rawsetp(L: *mut lua_State, idx: c_int, p: *const libc::c_char);
     pub fn lua_setmetatable(L: *mut lua_State, objindex: c_int) -> c_int;
+    pub fn lua_setuservalue(L: *mut lua_State, idx: c_int);
     pub fn lua_setfenv(L: *mut lua_State, idx: c_int) -> c_int;
 
     pub fn lua_callk(L: *mut lua_State,
@@ -260,7 +269,11 @@ extern "C" {
     pub fn luaL_newstate() -> *mut lua_State;
     pub fn luaL_loadstring(L: *mut lua_State, p: *const libc::c_char) -> c_int;
     pub fn luaL_setmetatable(L: *mut lua_State, tname: *const libc::c_char);
-    pub fn luaL_error(L: *mut lua_State, info: *const libc::c_char);
+    pub fn luaL_error(L: *mut lua_State, fmt: *const libc::c_char, ...) -> c_int;
+    pub fn luaL_where(L: *mut lua_State, lvl: c_int);
+    pub fn luaL_ref(L: *mut lua_State, t: c_int) -> c_int;
+    pub fn luaL_unref(L: *mut lua_State, t: c_int, r: c_int);
+    pub fn luaL_traceback(L: *mut lua_State, L1: *mut lua_State, msg: *const libc::c_char, level: c_int);
     pub fn luaL_loadbufferx(L: *mut lua_State,
                             buff: *const libc::c_char,
                             sz: libc::size_t,
@@ -354,7 +367,7 @@ pub unsafe fn lua_isnoneornil(L: *mut lua_State, idx: c_int) -> bool {
 
 #[inline(always)]
 pub unsafe fn lua_pushglobaltable(L: *mut lua_State) {
-    lua_rawgeti(L, LUA_REGISTRYINDEX, LUA_RIDX_GLOBALS)
+    lua_rawgeti(L, LUA_REGISTRYINDEX, LUA_RIDX_GLOBALS as lua_Integer)
 }
 
 #[inline(always)]
@@ -371,6 +384,11 @@ pub unsafe fn lua_insert(L: *mut lua_State, idx: c_int) {
     lua_rotate(L, idx, 1);
 }
 
+pub unsafe fn lua_replace(L: *mut lua_State, idx: c_int) {
+    lua_copy(L, -1, idx);
+    lua_pop(L, 1);
+}
+
 pub unsafe fn luaL_loadbuffer(L: *mut lua_State,
                               buff: *const libc::c_char,
                               sz: libc::size_t,
@@ -395,6 +413,7 @@ impl default::Default for lua_Debug {
             isvararg: 0,
             istailcall: 0,
             short_src: [0; 60],
+            i_ci: ptr::null_mut(),
         }
     }
 }
@@ -0,0 +1,109 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use libc;
+
+use td_clua::{self, lua_State};
+use LuaRead;
+
+// message handler appending the Lua stack traceback to string errors, as `debug.traceback`
+pub(crate) extern "C" fn traceback_handler(lua: *mut lua_State) -> libc::c_int {
+    unsafe {
+        let msg = td_clua::lua_tostring(lua, 1);
+        if !msg.is_null() {
+            td_clua::luaL_traceback(lua, lua, msg, 1);
+        }
+    }
+    1
+}
+
+/// Error raised while loading or running Lua code.
+#[derive(Debug)]
+pub enum LuaError {
+    /// The chunk failed to compile (`LUA_ERRSYNTAX`).
+    Syntax(String),
+    /// An error was raised while running Lua code (`LUA_ERRRUN`).
+    Runtime(String),
+    /// Lua failed to allocate memory (`LUA_ERRMEM`).
+    Memory(String),
+    /// A `__gc` metamethod raised an error (`LUA_ERRGCMM`).
+    Gc(String),
+    /// The message handler itself raised an error (`LUA_ERRERR`).
+    MessageHandler(String),
+    /// Reading the source of a chunk failed.
+    Io(io::Error),
+    /// The code was still running when its deadline passed.
+    Timeout,
+}
+
+impl LuaError {
+    /// Builds the error matching a non-zero status returned by `lua_load` or `lua_pcall`,
+    /// popping the error object from the top of the stack.
+    pub fn from_status(lua: *mut lua_State, status: i32) -> LuaError {
+        let msg: String = LuaRead::lua_read(lua).unwrap_or_else(|| "(error object is not a string)".to_string());
+        unsafe { td_clua::lua_pop(lua, 1) };
+        match status {
+            td_clua::LUA_ERRSYNTAX => LuaError::Syntax(msg),
+            td_clua::LUA_ERRMEM => LuaError::Memory(msg),
+            td_clua::LUA_ERRGCMM => LuaError::Gc(msg),
+            td_clua::LUA_ERRERR => LuaError::MessageHandler(msg),
+            _ => LuaError::Runtime(msg),
+        }
+    }
+
+    /// The message carried by the error, as Lua reported it.
+    pub fn message(&self) -> String {
+        match *self {
+            LuaError::Syntax(ref msg) |
+            LuaError::Runtime(ref msg) |
+            LuaError::Memory(ref msg) |
+            LuaError::Gc(ref msg) |
+            LuaError::MessageHandler(ref msg) => msg.clone(),
+            LuaError::Io(ref err) => err.to_string(),
+            LuaError::Timeout => "deadline exceeded".to_string(),
+        }
+    }
+
+    /// The Lua stack traceback appended to the message by a handler such as
+    /// `debug.traceback`, if any.
+    pub fn traceback(&self) -> Option<&str> {
+        match *self {
+            LuaError::Runtime(ref msg) |
+            LuaError::Gc(ref msg) |
+            LuaError::MessageHandler(ref msg) => {
+                msg.find("stack traceback:").map(|pos| &msg[pos..])
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for LuaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LuaError::Syntax(ref msg) => write!(f, "syntax error: {}", msg),
+            LuaError::Runtime(ref msg) => write!(f, "runtime error: {}", msg),
+            LuaError::Memory(ref msg) => write!(f, "memory error: {}", msg),
+            LuaError::Gc(ref msg) => write!(f, "error in __gc metamethod: {}", msg),
+            LuaError::MessageHandler(ref msg) => write!(f, "error in message handler: {}", msg),
+            LuaError::Io(ref err) => write!(f, "read error: {}", err),
+            LuaError::Timeout => write!(f, "deadline exceeded"),
+        }
+    }
+}
+
+impl Error for LuaError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            LuaError::Io(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for LuaError {
+    fn from(err: io::Error) -> LuaError {
+        LuaError::Io(err)
+    }
+}
@@ -1,11 +1,19 @@
 use libc;
 use td_clua::{self, lua_State};
+use std::ffi::CString;
 use LuaRead;
 use LuaPush;
 
+use std::any::Any;
+use std::backtrace::{Backtrace, BacktraceStatus};
+use std::cell::RefCell;
 use std::marker::PhantomData;
 use std::mem;
+use std::panic::{self, AssertUnwindSafe};
 use std::ptr;
+use std::sync::Once;
+
+use userdata::{push_userdata, read_userdata, Internal};
 
 macro_rules! impl_function {
     ($name:ident, $($p:ident),*) => (
@@ -33,6 +41,117 @@ impl_function!(function8, A, B, C, D, E, F, G, H);
 impl_function!(function9, A, B, C, D, E, F, G, H, I);
 impl_function!(function10, A, B, C, D, E, F, G, H, I, J);
 
+/// Whether an argument was given to a native function, see `Lua::arg_presence`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArgPresence {
+    /// The caller passed fewer arguments.
+    Absent,
+    /// The caller passed an explicit nil.
+    Nil,
+    /// The caller passed any other value.
+    Present,
+}
+
+/// What happens when a Rust function called from Lua panics.
+pub enum PanicBehavior {
+    /// The panic reaches the Lua call boundary and aborts the process. This is the default.
+    Abort,
+    /// The panic is caught and raised as a Lua error carrying the panic message.
+    ConvertToError,
+    /// The panic is caught and the handler turns its message into the Lua error to raise.
+    Custom(Box<dyn Fn(&str) -> String>),
+}
+
+// registry key of the `PanicBehavior` of a state
+const PANIC_BEHAVIOR_KEY: &str = "__td_rlua_panic_behavior";
+
+/// Sets the panic behavior of every Rust function called from this state.
+pub fn set_panic_behavior(lua: *mut lua_State, behavior: PanicBehavior) {
+    push_userdata(Internal(behavior), lua, |_| {});
+    let key = CString::new(PANIC_BEHAVIOR_KEY).unwrap();
+    unsafe { td_clua::lua_setfield(lua, td_clua::LUA_REGISTRYINDEX, key.as_ptr()) };
+}
+
+// registry key set when converted panics carry their Rust location
+const PANIC_TRACEBACK_KEY: &str = "__td_rlua_panic_traceback";
+
+thread_local! {
+    // location and backtrace of the last panic of this thread, set by the hook
+    static LAST_PANIC: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+static PANIC_HOOK: Once = Once::new();
+
+// chains a hook recording where the panics of every thread happen
+fn install_panic_hook() {
+    PANIC_HOOK.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            let mut place = match info.location() {
+                Some(location) => format!("{}:{}", location.file(), location.line()),
+                None => "unknown location".to_string(),
+            };
+            let backtrace = Backtrace::capture();
+            if backtrace.status() == BacktraceStatus::Captured {
+                place = format!("{}\nstack backtrace:\n{}", place, backtrace);
+            }
+            LAST_PANIC.with(|last| *last.borrow_mut() = Some(place));
+            previous(info);
+        }));
+    });
+}
+
+/// Appends the Rust source location of the panic to the messages of converted panics, and
+/// the Rust backtrace when `RUST_BACKTRACE` enables it.
+pub fn set_panic_traceback(lua: *mut lua_State, enable: bool) {
+    if enable {
+        install_panic_hook();
+    }
+    let key = CString::new(PANIC_TRACEBACK_KEY).unwrap();
+    unsafe {
+        td_clua::lua_pushboolean(lua, enable as libc::c_int);
+        td_clua::lua_setfield(lua, td_clua::LUA_REGISTRYINDEX, key.as_ptr());
+    }
+}
+
+// takes the location recorded for the last panic if the state asked for it
+fn panic_location(lua: *mut lua_State) -> Option<String> {
+    let place = LAST_PANIC.with(|last| last.borrow_mut().take());
+    let key = CString::new(PANIC_TRACEBACK_KEY).unwrap();
+    let enabled = unsafe {
+        td_clua::lua_getfield(lua, td_clua::LUA_REGISTRYINDEX, key.as_ptr());
+        let enabled = td_clua::lua_toboolean(lua, -1) != 0;
+        td_clua::lua_pop(lua, 1);
+        enabled
+    };
+    if enabled { place } else { None }
+}
+
+// turns a caught panic into the message of the Lua error to raise, or resumes it
+pub(crate) fn panic_message(lua: *mut lua_State, payload: Box<dyn Any + Send>) -> String {
+    let message = match payload.downcast_ref::<&str>() {
+        Some(msg) => msg.to_string(),
+        None => match payload.downcast_ref::<String>() {
+            Some(msg) => msg.clone(),
+            None => "Box<Any>".to_string(),
+        },
+    };
+
+    let key = CString::new(PANIC_BEHAVIOR_KEY).unwrap();
+    unsafe { td_clua::lua_getfield(lua, td_clua::LUA_REGISTRYINDEX, key.as_ptr()) };
+    let message = match read_userdata::<Internal<PanicBehavior>>(lua, -1) {
+        Some(&mut Internal(PanicBehavior::ConvertToError)) => Some(message),
+        Some(&mut Internal(PanicBehavior::Custom(ref handler))) => Some(handler(&message)),
+        _ => None,
+    };
+    unsafe { td_clua::lua_pop(lua, 1) };
+    match (message, panic_location(lua)) {
+        (Some(message), Some(place)) => format!("{} (panicked at {})", message, place),
+        (Some(message), None) => message,
+        (None, _) => panic::resume_unwind(payload),
+    }
+}
+
 /// Opaque type containing a Rust function or closure.
 pub struct Function<F, P, R> {
     function: F,
@@ -149,9 +268,18 @@ extern "C" fn wrapper<T, P, R>(lua: *mut td_clua::lua_State) -> libc::c_int
 
     };
 
-    let ret_value = data.call_mut(args);
+    let err_msg = match panic::catch_unwind(AssertUnwindSafe(|| data.call_mut(args))) {
+        Ok(ret_value) => {
+            // pushing back the result of the function on the stack
+            let nb = ret_value.push_to_lua(lua);
+            return nb as libc::c_int;
+        }
+        Err(payload) => panic_message(lua, payload),
+    };
 
-    // pushing back the result of the function on the stack
-    let nb = ret_value.push_to_lua(lua);
-    nb as libc::c_int
+    err_msg.push_to_lua(lua);
+    unsafe {
+        td_clua::lua_error(lua);
+    }
+    unreachable!()
 }
@@ -0,0 +1,154 @@
+use std::ffi::CStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+use libc;
+use td_clua::{self, lua_State, lua_Debug};
+
+use userdata::{push_userdata, read_userdata, Internal};
+
+// debug hooks, the closures are kept in the registry of the state
+
+// registry key of the closure called by `line_hook`
+const LINE_HOOK_KEY: &[u8] = b"__td_rlua_line_hook\0";
+
+// installed with LUA_MASKLINE, resolves the current source and line for the closure
+extern "C" fn line_hook<F>(lua: *mut lua_State, ar: *mut lua_Debug)
+    where F: FnMut(&str, u32) + 'static
+{
+    unsafe {
+        if td_clua::lua_getinfo(lua, b"Sl\0".as_ptr() as *const libc::c_char, ar) == 0 {
+            return;
+        }
+        td_clua::lua_getfield(lua, td_clua::LUA_REGISTRYINDEX, LINE_HOOK_KEY.as_ptr() as *const libc::c_char);
+        // the closure stays referenced by the registry once popped
+        let hook = read_userdata::<Internal<F>>(lua, -1);
+        td_clua::lua_pop(lua, 1);
+        if let Some(&mut Internal(ref mut hook)) = hook {
+            let source = CStr::from_ptr((*ar).short_src.as_ptr()).to_string_lossy();
+            hook(&source, (*ar).currentline as u32);
+        }
+    }
+}
+
+/// Calls `hook` with the source and the line number each time Lua starts running a new
+/// line, replacing any hook set on this state before.
+pub fn set_line_hook<F>(lua: *mut lua_State, hook: F)
+    where F: FnMut(&str, u32) + 'static
+{
+    push_userdata(Internal(hook), lua, |_| {});
+    unsafe {
+        td_clua::lua_setfield(lua, td_clua::LUA_REGISTRYINDEX, LINE_HOOK_KEY.as_ptr() as *const libc::c_char);
+        td_clua::lua_sethook(lua, line_hook::<F>, td_clua::LUA_MASKLINE, 0);
+    }
+}
+
+// instructions run between two checks of the clock or of the interrupt flag
+const DEADLINE_CHECK_INTERVAL: i32 = 1000;
+
+// registry key of the flag checked by `interrupt_hook`
+const INTERRUPT_KEY: &[u8] = b"__td_rlua_interrupt\0";
+
+// installed with LUA_MASKCOUNT, raises an error while the flag is set
+extern "C" fn interrupt_hook(lua: *mut lua_State, _: *mut lua_Debug) {
+    let interrupted = unsafe {
+        td_clua::lua_getfield(lua, td_clua::LUA_REGISTRYINDEX, INTERRUPT_KEY.as_ptr() as *const libc::c_char);
+        let flag = read_userdata::<Internal<Arc<AtomicBool>>>(lua, -1).is_some_and(|flag| flag.0.load(Ordering::Relaxed));
+        td_clua::lua_pop(lua, 1);
+        flag
+    };
+    if interrupted {
+        unsafe { td_clua::luaL_error(lua, b"interrupted\0".as_ptr() as *const libc::c_char) };
+    }
+}
+
+/// Checks `flag` every thousand instructions and raises an error while it is set, replacing
+/// any hook set on this state before.
+pub fn set_interrupt(lua: *mut lua_State, flag: Arc<AtomicBool>) {
+    push_userdata(Internal(flag), lua, |_| {});
+    unsafe {
+        td_clua::lua_setfield(lua, td_clua::LUA_REGISTRYINDEX, INTERRUPT_KEY.as_ptr() as *const libc::c_char);
+        td_clua::lua_sethook(lua, interrupt_hook, td_clua::LUA_MASKCOUNT, DEADLINE_CHECK_INTERVAL);
+    }
+}
+
+// registry key of the `Deadline` checked by `deadline_hook`
+const DEADLINE_KEY: &[u8] = b"__td_rlua_deadline\0";
+
+struct Deadline {
+    at: Instant,
+    expired: bool,
+}
+
+// installed with LUA_MASKCOUNT, raises an error once the deadline has passed
+extern "C" fn deadline_hook(lua: *mut lua_State, _: *mut lua_Debug) {
+    let expired = unsafe {
+        td_clua::lua_getfield(lua, td_clua::LUA_REGISTRYINDEX, DEADLINE_KEY.as_ptr() as *const libc::c_char);
+        let deadline = read_userdata::<Deadline>(lua, -1);
+        td_clua::lua_pop(lua, 1);
+        match deadline {
+            Some(deadline) if Instant::now() >= deadline.at => {
+                deadline.expired = true;
+                true
+            }
+            _ => false,
+        }
+    };
+    if expired {
+        unsafe { td_clua::luaL_error(lua, b"deadline exceeded\0".as_ptr() as *const libc::c_char) };
+    }
+}
+
+// puts back the hook set before `with_deadline` and removes its deadline when dropped,
+// unwinding included
+struct DeadlineRestore {
+    lua: *mut lua_State,
+    previous: Option<(td_clua::lua_Hook, libc::c_int, libc::c_int)>,
+}
+
+impl Drop for DeadlineRestore {
+    fn drop(&mut self) {
+        unsafe {
+            td_clua::lua_pushnil(self.lua);
+            td_clua::lua_setfield(self.lua, td_clua::LUA_REGISTRYINDEX, DEADLINE_KEY.as_ptr() as *const libc::c_char);
+            match self.previous {
+                Some((hook, mask, count)) => td_clua::lua_sethook(self.lua, hook, mask, count),
+                None => td_clua::lua_sethook(self.lua, deadline_hook, 0, 0),
+            };
+        }
+    }
+}
+
+/// Runs `f` with a hook raising an error once `deadline` has passed, checked every
+/// thousand instructions. Returns what `f` returned, and whether the deadline expired.
+///
+/// The hook set on the state before, if any, is put back afterwards, also when `f` panics.
+pub fn with_deadline<F, R>(lua: *mut lua_State, deadline: Instant, f: F) -> (R, bool)
+    where F: FnOnce() -> R
+{
+    let previous = unsafe {
+        // lua_gethook is only non-null when a mask is set
+        match td_clua::lua_gethookmask(lua) {
+            0 => None,
+            mask => Some((td_clua::lua_gethook(lua), mask, td_clua::lua_gethookcount(lua))),
+        }
+    };
+
+    push_userdata(Deadline { at: deadline, expired: false }, lua, |_| {});
+    let _restore = DeadlineRestore { lua, previous };
+    unsafe {
+        td_clua::lua_setfield(lua, td_clua::LUA_REGISTRYINDEX, DEADLINE_KEY.as_ptr() as *const libc::c_char);
+        td_clua::lua_sethook(lua, deadline_hook, td_clua::LUA_MASKCOUNT, DEADLINE_CHECK_INTERVAL);
+    }
+
+    let result = f();
+
+    let expired = unsafe {
+        td_clua::lua_getfield(lua, td_clua::LUA_REGISTRYINDEX, DEADLINE_KEY.as_ptr() as *const libc::c_char);
+        let expired = read_userdata::<Deadline>(lua, -1).is_some_and(|deadline| deadline.expired);
+        td_clua::lua_pop(lua, 1);
+        expired
+    };
+    (result, expired)
+}
@@ -1,10 +1,17 @@
 extern crate td_clua;
 extern crate libc;
+#[cfg(feature = "uuid")]
+extern crate uuid;
 
 use std::borrow::Borrow;
+use std::error::Error;
 use std::ffi::{CStr, CString};
+use std::fmt;
 use std::io::prelude::*;
 use std::fs::File;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::time::{Duration, Instant};
 
 macro_rules! unwrap_or {
     ($expr:expr, $or:expr) => (
@@ -15,18 +22,35 @@ macro_rules! unwrap_or {
     )
 }
 
+#[macro_use]
+mod macros;
+
 pub mod values;
 pub mod lua_tables;
 pub mod functions;
 pub mod userdata;
 pub mod tuples;
 pub mod rust_tables;
+pub mod lua_value;
+pub mod lua_function;
+pub mod lua_coroutine;
+pub mod error;
+pub mod hooks;
 mod hotfix;
+mod utf8;
 
 pub use td_clua::*;
-pub use functions::{function0, function1, function2, function3, function4, function5, function6, function7, function8, function9, function10, Function};
-pub use userdata::{push_userdata, push_lightuserdata, read_userdata, LuaStruct, NewStruct};
-pub use lua_tables::LuaTable;
+pub use functions::{function0, function1, function2, function3, function4, function5, function6, function7, function8, function9, function10, ArgPresence, Function,
+                    PanicBehavior};
+pub use userdata::{push_userdata, push_userdata_cached, push_lightuserdata, read_userdata, read_userdata_ref, push_weak_userdata, read_weak_userdata,
+                   push_shared_userdata, LuaStruct, NewStruct};
+pub use lua_tables::{GlobalsSnapshot, LuaTable};
+pub use lua_value::LuaValue;
+pub use lua_function::{LuaChunk, LuaFunction};
+pub use lua_coroutine::{CoroutineStatus, LuaCoroutine};
+pub use error::LuaError;
+pub use values::{push_str_from, Ascii, Finite, Lenient, LuaInstant, Truthy};
+pub use rust_tables::Pairs;
 pub struct Lua {
     lua: *mut lua_State,
     own: bool,
@@ -69,6 +93,54 @@ impl LuaGuard {
 }
 
 
+// state of a chunk streamed by `Lua::load_reader`
+struct ChunkReader<R> {
+    reader: R,
+    buffer: Vec<u8>,
+    error: Option<std::io::Error>,
+}
+
+// called by `lua_load` each time it needs the next piece of the chunk
+extern "C" fn chunk_reader<R>(_: *mut lua_State, data: *mut libc::c_void, size: *mut libc::size_t) -> *const libc::c_char
+    where R: Read
+{
+    let data = unsafe { &mut *(data as *mut ChunkReader<R>) };
+    loop {
+        match data.reader.read(&mut data.buffer) {
+            Ok(read) => {
+                unsafe { *size = read };
+                return data.buffer.as_ptr() as *const libc::c_char;
+            }
+            Err(ref err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(err) => {
+                // ending the chunk here, the error is reported once lua_load returns
+                data.error = Some(err);
+                unsafe { *size = 0 };
+                return std::ptr::null();
+            }
+        }
+    }
+}
+
+// closure run by `Lua::protected`, and what came out of it
+struct ProtectedCall<F, R> {
+    func: Option<F>,
+    result: Option<std::thread::Result<R>>,
+}
+
+// called through lua_pcall with the `ProtectedCall` as its only argument
+extern "C" fn protected_call<F, R>(lua: *mut lua_State) -> libc::c_int
+    where F: FnOnce(&mut Lua) -> R
+{
+    let data = unsafe { &mut *(td_clua::lua_touserdata(lua, 1) as *mut ProtectedCall<F, R>) };
+    unsafe { td_clua::lua_pop(lua, 1) };
+    let func = data.func.take().unwrap();
+    let mut lua = Lua::from_existing_state(lua, false);
+    // a panic can't unwind through Lua, it is resumed once lua_pcall returns
+    data.result = Some(std::panic::catch_unwind(std::panic::AssertUnwindSafe(move || func(&mut lua))));
+    0
+}
+
 macro_rules! impl_exec_func {
     ($name:ident, $($p:ident),*) => (
         #[allow(non_snake_case, unused_mut)]
@@ -149,6 +221,28 @@ impl Lua {
         self.own = own;
     }
 
+    /// Returns true if `lua_close` is called on the state when this object is dropped.
+    pub fn is_owned(&self) -> bool {
+        self.own
+    }
+
+    /// Gives up ownership and returns the state, which is left open.
+    ///
+    /// The caller becomes responsible for closing it, for example by passing it back to
+    /// `from_existing_state` with `close_at_the_end` set.
+    pub fn into_raw(mut self) -> *mut lua_State {
+        self.own = false;
+        self.lua
+    }
+
+    /// Closes the state now if this object owns it, otherwise only releases the handle.
+    pub fn close(mut self) {
+        if self.own {
+            unsafe { td_clua::lua_close(self.lua) };
+            self.own = false;
+        }
+    }
+
     /// Takes an existing `lua_State` and build a Lua object from it.
     ///
     /// # Arguments
@@ -161,6 +255,17 @@ impl Lua {
         }
     }
 
+    /// Wraps the state received by a native function, so that the function can use the whole
+    /// API, including calling back into Lua.
+    ///
+    /// The returned object never closes the state, dropping it is always safe.
+    pub fn from_callback(lua: *mut lua_State) -> Lua {
+        Lua::from_existing_state(lua, false)
+    }
+
+    /// Sets the native function `func` as the global `index`, this is `lua_register`.
+    ///
+    /// Returns 0.
     pub fn register<I>(&mut self, index : I, func : extern "C" fn(*mut td_clua::lua_State) -> libc::c_int) -> i32
                     where I: Borrow<str>
     {
@@ -194,6 +299,28 @@ impl Lua {
         unsafe { td_clua::lua_setglobal(self.lua, index.as_ptr()); }
     }
 
+    /// Stores `value` in the registry under `key`, where scripts can't reach it but native
+    /// functions running in the state can, see `get_registry`.
+    ///
+    /// Keys starting with `_` are used by Lua and this crate, prefer a name of your own.
+    pub fn set_registry<V>(&mut self, key: &str, value: V)
+        where V: LuaPush
+    {
+        let key = CString::new(key).unwrap();
+        value.push_to_lua(self.state());
+        unsafe { td_clua::lua_setfield(self.lua, td_clua::LUA_REGISTRYINDEX, key.as_ptr()) };
+    }
+
+    /// Reads the value stored in the registry under `key` by `set_registry`.
+    pub fn get_registry<V>(&mut self, key: &str) -> Option<V>
+        where V: LuaRead
+    {
+        let key = CString::new(key).unwrap();
+        unsafe { td_clua::lua_getfield(self.lua, td_clua::LUA_REGISTRYINDEX, key.as_ptr()) };
+        let _guard = LuaGuard::new(self.lua, 1);
+        LuaRead::lua_read(self.state())
+    }
+
     pub fn exec_string<'a, I, R>(&'a mut self, index : I) -> Option<R>
                             where I: Borrow<str>, R : LuaRead
     {
@@ -233,6 +360,38 @@ impl Lua {
         }
     }
 
+    /// Pushes a new array table filled from `iter` without collecting it first.
+    ///
+    /// Stack effect: +1.
+    pub fn push_iter<T, I>(&mut self, iter: I) -> i32
+        where T: LuaPush, I: IntoIterator<Item=T>
+    {
+        rust_tables::push_array(self.state(), iter)
+    }
+
+    /// Returns the sorted names of the global variables, skipping keys of `_G` that aren't
+    /// strings.
+    pub fn global_names(&mut self) -> Vec<String> {
+        let mut names = vec![];
+        unsafe {
+            let state = self.state();
+            td_clua::lua_pushglobaltable(state);
+            td_clua::lua_pushnil(state);
+            while td_clua::lua_next(state, -2) != 0 {
+                // only reading string keys, lua_tolstring would convert numbers in place
+                if td_clua::lua_type(state, -2) == td_clua::LUA_TSTRING {
+                    if let Some(name) = LuaRead::lua_read_at_position(state, -2) {
+                        names.push(name);
+                    }
+                }
+                td_clua::lua_pop(state, 1);
+            }
+            td_clua::lua_pop(state, 1);
+        }
+        names.sort();
+        names
+    }
+
     /// Inserts an empty table, then loads it.
     pub fn empty_table<I>(&mut self, index: I) -> LuaTable
                               where I: Borrow<str>
@@ -245,6 +404,53 @@ impl Lua {
         self.query(index).unwrap()
     }
 
+    /// Pushes a new table with room preallocated for `narr` array elements and `nrec` other
+    /// entries, then loads it. The table is popped when the LuaTable is dropped.
+    pub fn new_table_sized(&mut self, narr: usize, nrec: usize) -> LuaTable {
+        LuaTable::push_sized(self.state(), narr, nrec)
+    }
+
+    /// Saves the content of the global table and of every table reachable from it, for
+    /// `restore_globals` to undo whatever scripts change in the globals meanwhile, such as
+    /// between two tests sharing a state.
+    pub fn snapshot_globals(&mut self) -> GlobalsSnapshot {
+        GlobalsSnapshot::take(self.state())
+    }
+
+    /// Resets the globals to `snapshot`: globals added since are removed, the others get back
+    /// their value, and the snapshot can be restored again.
+    ///
+    /// Tables are refilled in place rather than replaced, so the standard library tables stay
+    /// the ones known to `require` and to the string metatable. Metatables aren't followed,
+    /// and tables reached only from one are left as they are.
+    pub fn restore_globals(&mut self, snapshot: &GlobalsSnapshot) {
+        snapshot.restore(self.state())
+    }
+
+    /// Pushes a new table holding each native function of `entries` under its name, such as
+    /// a module built from a registry of functions, then loads it.
+    ///
+    /// Stack effect: +1, popped when the LuaTable is dropped.
+    pub fn register_fn_table(&mut self,
+                             entries: &[(&str, extern "C" fn(*mut td_clua::lua_State) -> libc::c_int)])
+                             -> LuaTable {
+        let mut table = self.new_table_sized(0, entries.len());
+        for &(name, func) in entries {
+            table.register(name, func);
+        }
+        table
+    }
+
+    /// Inserts an empty table whose missing keys read as a clone of `default`, then loads it.
+    pub fn make_defaulting_table<I, D>(&mut self, index: I, default: D) -> LuaTable
+                              where I: Borrow<str>,
+                                    D: LuaPush + Clone + 'static
+    {
+        let mut table = self.empty_table(index);
+        table.set_default(default);
+        table
+    }
+
     pub fn add_lualoader(&mut self, func : extern "C" fn(*mut td_clua::lua_State) -> libc::c_int) -> i32 {
         let state = self.state();
         unsafe {
@@ -253,7 +459,7 @@ impl Lua {
             td_clua::lua_getglobal(state, package.as_ptr());
             td_clua::lua_getfield(state, -1, searchers.as_ptr());
             td_clua::lua_pushcfunction(state, func);
-            let mut i = (td_clua::lua_rawlen(state, -2) + 1) as i32;
+            let mut i = (td_clua::lua_rawlen(state, -2) + 1) as td_clua::lua_Integer;
             while i > 2 {
                 td_clua::lua_rawgeti(state, -2, i - 1);                               
                 td_clua::lua_rawseti(state, -3, i);
@@ -288,15 +494,532 @@ impl Lua {
         1
     }
 
+    /// Compares the values at `index1` and `index2` with `==`, calling `__eq` if needed.
+    pub fn equal(&mut self, index1: i32, index2: i32) -> bool {
+        unsafe { td_clua::lua_compare(self.state(), index1, index2, td_clua::LUA_OPEQ) != 0 }
+    }
+
+    /// Compares the values at `index1` and `index2` without calling metamethods.
+    pub fn raw_equal(&mut self, index1: i32, index2: i32) -> bool {
+        unsafe { td_clua::lua_rawequal(self.state(), index1, index2) != 0 }
+    }
+
+    /// Returns true if the value at `index` is a number equal to `value`, without converting
+    /// strings or reading the value out.
+    pub fn number_eq(&self, index: i32, value: f64) -> bool {
+        unsafe {
+            td_clua::lua_type(self.lua, index) == td_clua::LUA_TNUMBER &&
+                td_clua::lua_tonumberx(self.lua, index, std::ptr::null_mut()) == value
+        }
+    }
+
+    /// Returns true if the value at `index` is a number with the integer value `value`,
+    /// a float with an exact integer value matches too, like with `==` in Lua.
+    pub fn integer_eq(&self, index: i32, value: i64) -> bool {
+        unsafe {
+            if td_clua::lua_type(self.lua, index) != td_clua::LUA_TNUMBER {
+                return false;
+            }
+            let mut success = 0;
+            let val = td_clua::lua_tointegerx(self.lua, index, &mut success);
+            success != 0 && val as i64 == value
+        }
+    }
+
+    /// Returns true if the value at `index` is a string holding exactly the bytes of `value`,
+    /// numbers don't match.
+    pub fn str_eq(&self, index: i32, value: &str) -> bool {
+        unsafe {
+            if td_clua::lua_type(self.lua, index) != td_clua::LUA_TSTRING {
+                return false;
+            }
+            let mut size = 0;
+            let ptr = td_clua::lua_tolstring(self.lua, index, &mut size);
+            std::slice::from_raw_parts(ptr as *const u8, size) == value.as_bytes()
+        }
+    }
+
+    /// Raises a Lua error from a native function, prefixed with the position of the
+    /// calling Lua code like `luaL_error` does.
+    ///
+    /// This never returns: Lua longjmps out of the native function, so no Rust value still
+    /// alive in the calling frames is dropped. The message is taken by value and released
+    /// before raising, other values must be dropped by the caller beforehand.
+    pub fn raise_error<S>(&mut self, msg: S) -> !
+                        where S: Borrow<str>
+    {
+        push_str_from(self.state(), msg.borrow());
+        drop(msg);
+        unsafe {
+            let msg = td_clua::lua_tostring(self.state(), -1);
+            td_clua::luaL_error(self.state(), b"%s\0".as_ptr() as *const libc::c_char, msg);
+        }
+        unreachable!()
+    }
+
+    /// Returns the `chunkname:currentline: ` prefix of the function at call stack `level`, as
+    /// put in front of the messages of Lua's own errors. This is `luaL_where`, which can't keep
+    /// its name since `where` is a keyword.
+    ///
+    /// Level 1 is the function that called the running native function. The prefix is empty
+    /// when no source information is available.
+    pub fn location(&mut self, level: i32) -> String {
+        unsafe { td_clua::luaL_where(self.state(), level) };
+        let _guard = LuaGuard::new(self.lua, 1);
+        LuaRead::lua_read(self.state()).unwrap_or_default()
+    }
+
+    /// Sets what happens when a Rust function registered in this state panics.
+    pub fn set_panic_behavior(&mut self, behavior: PanicBehavior) {
+        functions::set_panic_behavior(self.state(), behavior);
+    }
+
+    /// When enabled, the Lua errors made from panics (see `set_panic_behavior`) end with the
+    /// `file:line` of the panic in the Rust code, followed by the Rust backtrace when
+    /// `RUST_BACKTRACE` is set.
+    ///
+    /// Enabling it installs a process-wide panic hook, chained to the previous one.
+    pub fn traceback_on_panic(&mut self, enable: bool) {
+        functions::set_panic_traceback(self.state(), enable);
+    }
+
+    /// Sets how deeply tables may nest when a `LuaValue` is pushed or read in this state,
+    /// `lua_value::DEFAULT_MAX_TABLE_DEPTH` by default. Deeper values fail to read, and fail
+    /// `LuaValue::try_push`, instead of exhausting the stack.
+    pub fn set_max_table_depth(&mut self, depth: usize) {
+        lua_value::set_max_table_depth(self.state(), depth);
+    }
+
+    /// Loads a chunk from `reader` piece by piece and runs it.
+    ///
+    /// The source is never buffered as a whole. If reading fails midway, nothing is run and
+    /// the IO error is returned.
+    pub fn load_reader<R>(&mut self, reader: R, chunk_name: &str) -> Result<(), LuaError>
+                        where R: Read
+    {
+        let mut data = ChunkReader {
+            reader,
+            buffer: vec![0; 4096],
+            error: None,
+        };
+        let chunk_name = CString::new(chunk_name).unwrap();
+        let state = self.state();
+        let ret = unsafe {
+            td_clua::lua_load(state, chunk_reader::<R>, &mut data as *mut ChunkReader<R> as *mut libc::c_void,
+                              chunk_name.as_ptr(), std::ptr::null())
+        };
+        if let Some(err) = data.error {
+            unsafe { td_clua::lua_pop(state, 1) };
+            return Err(LuaError::Io(err));
+        }
+        if ret != 0 {
+            return Err(LuaError::from_status(state, ret));
+        }
+
+        let ret = unsafe { td_clua::lua_pcall(state, 0, 0, 0) };
+        if ret != 0 {
+            return Err(LuaError::from_status(state, ret));
+        }
+        Ok(())
+    }
+
+    /// Loads `code` as a chunk called `chunk_name` and runs it.
+    ///
+    /// The name replaces the `[string "..."]` snippet in error messages and tracebacks, so a
+    /// logical name such as a module path can be given. A runtime error comes with the Lua
+    /// stack traceback, see `LuaError::traceback`.
+    pub fn load_string_named(&mut self, code: &str, chunk_name: &str) -> Result<(), LuaError> {
+        let state = self.state();
+        // a leading '=' keeps the name as is instead of quoting it as source
+        let chunk_name = CString::new(format!("={}", chunk_name)).unwrap();
+        let ret = unsafe {
+            td_clua::luaL_loadbuffer(state, code.as_ptr() as *const libc::c_char, code.len(), chunk_name.as_ptr())
+        };
+        if ret != 0 {
+            return Err(LuaError::from_status(state, ret));
+        }
+
+        unsafe {
+            td_clua::lua_pushcfunction(state, error::traceback_handler);
+            td_clua::lua_insert(state, -2);
+        }
+        let ret = unsafe { td_clua::lua_pcall(state, 0, 0, -2) };
+        let result = if ret != 0 { Err(LuaError::from_status(state, ret)) } else { Ok(()) };
+        // removing the message handler
+        unsafe { td_clua::lua_pop(state, 1) };
+        result
+    }
+
+    /// Loads and runs each of `chunks` in turn against the state, going on after a chunk fails,
+    /// and returns the outcome of every chunk.
+    ///
+    /// The chunks are run separately, so their locals aren't shared but their globals are.
+    /// They are named `chunk 1`, `chunk 2`... in error messages.
+    pub fn run_all(&mut self, chunks: &[&str]) -> Vec<Result<(), LuaError>> {
+        let state = self.state();
+        chunks.iter().enumerate().map(|(i, code)| {
+            let chunk_name = CString::new(format!("=chunk {}", i + 1)).unwrap();
+            let ret = unsafe {
+                td_clua::luaL_loadbuffer(state, code.as_ptr() as *const libc::c_char, code.len(), chunk_name.as_ptr())
+            };
+            if ret != 0 {
+                return Err(LuaError::from_status(state, ret));
+            }
+            let ret = unsafe { td_clua::lua_pcall(state, 0, 0, 0) };
+            if ret != 0 {
+                return Err(LuaError::from_status(state, ret));
+            }
+            Ok(())
+        }).collect()
+    }
+
+    /// Takes a handle to the function at `index`, such as a callback passed to a native
+    /// function, that stays valid once the value is off the stack. Returns `None` if the value
+    /// isn't a function.
+    pub fn ref_function(&mut self, index: i32) -> Option<LuaFunction> {
+        LuaRead::lua_read_at_position(self.state(), index)
+    }
+
+    /// Compiles `code` and leaves the resulting function on the top of the stack without
+    /// calling it, to be called, given an environment or referenced by the caller. A syntax
+    /// error is popped and returned.
+    ///
+    /// The chunk is called `chunk` in error messages, use `load_chunk` to give it a name.
+    /// This is the primitive `load_chunk` would otherwise be, a name already taken by the
+    /// helper returning a `LuaChunk`.
+    ///
+    /// Stack effect: +1 on success.
+    pub fn load(&mut self, code: &str) -> Result<(), LuaError> {
+        let name = CString::new("=chunk").unwrap();
+        lua_function::compile(self.state(), code, &name)
+    }
+
+    /// Compiles `code` as a chunk called `chunk_name` without running it, see `LuaChunk`.
+    pub fn load_chunk(&mut self, code: &str, chunk_name: &str) -> Result<LuaChunk, LuaError> {
+        LuaChunk::load(self.state(), code, chunk_name)
+    }
+
+    /// Compiles `code` with `env` in place of the global table and returns a coroutine that
+    /// runs it when first resumed.
+    ///
+    /// The sandboxes live in the same state but only see the variables of their own `env`,
+    /// which holds whatever functions they are allowed to call, and their globals are stored
+    /// there. A chunk that fails to compile is returned as an error.
+    pub fn spawn_sandbox(&mut self, code: &str, env: LuaTable) -> Result<LuaCoroutine, LuaError> {
+        let state = self.state();
+        let env_index = env.abs_index();
+        let chunk_name = CString::new("=sandbox").unwrap();
+        let ret = unsafe {
+            td_clua::luaL_loadbuffer(state, code.as_ptr() as *const libc::c_char, code.len(), chunk_name.as_ptr())
+        };
+        if ret != 0 {
+            return Err(LuaError::from_status(state, ret));
+        }
+        unsafe {
+            // a main chunk has `_ENV` as its only upvalue
+            td_clua::lua_pushvalue(state, env_index);
+            td_clua::lua_setupvalue(state, -2, 1);
+        }
+        let function = self.ref_function(-1).unwrap();
+        unsafe { td_clua::lua_pop(state, 1) };
+        Ok(LuaCoroutine::new(&function))
+    }
+
+    /// Calls the function below the `nargs` arguments on the top of the stack like
+    /// `lua_pcall`, with `handler` as the message handler: on error, it is called with the
+    /// error object and its result becomes the error.
+    ///
+    /// On success the function and its arguments are replaced by `nresults` results, or all
+    /// of them with `LUA_MULTRET`. On error they are popped and the error returned, its message
+    /// being the string the handler returned.
+    pub fn pcall_with_handler(&mut self, nargs: i32, nresults: i32, handler: LuaFunction) -> Result<(), LuaError> {
+        let state = self.state();
+        handler.push_to_lua(state);
+        let msgh = unsafe {
+            td_clua::lua_insert(state, -nargs - 2);
+            td_clua::lua_gettop(state) - nargs - 1
+        };
+        let ret = unsafe { td_clua::lua_pcall(state, nargs, nresults, msgh) };
+        let result = if ret != 0 { Err(LuaError::from_status(state, ret)) } else { Ok(()) };
+        unsafe { td_clua::lua_remove(state, msgh) };
+        result
+    }
+
+    /// Runs `f` inside a `lua_pcall`, so that a Lua error raised by any API call it makes is
+    /// returned as an `Err` instead of unwinding past the Rust frames of the caller.
+    ///
+    /// Values left on the stack by `f` are discarded. When an error is raised, the Rust values
+    /// owned by `f` at that moment are leaked rather than dropped, as with any Lua error
+    /// crossing Rust code. A panic inside `f` is resumed once back outside the protected call.
+    pub fn protected<F, R>(&mut self, f: F) -> Result<R, LuaError>
+                        where F: FnOnce(&mut Lua) -> R
+    {
+        let mut data = ProtectedCall {
+            func: Some(f),
+            result: None,
+        };
+        let state = self.state();
+        let ret = unsafe {
+            td_clua::lua_pushcfunction(state, protected_call::<F, R>);
+            td_clua::lua_pushlightuserdata(state, &mut data as *mut ProtectedCall<F, R> as *mut libc::c_void);
+            td_clua::lua_pcall(state, 1, 0, 0)
+        };
+        if ret != 0 {
+            return Err(LuaError::from_status(state, ret));
+        }
+        match data.result.take().unwrap() {
+            Ok(result) => Ok(result),
+            Err(payload) => std::panic::resume_unwind(payload),
+        }
+    }
+
+    /// Calls `f` with the source and the line number each time Lua starts running a new line,
+    /// as needed by a step debugger. The source is the `short_src` of the chunk, like the
+    /// prefix of error messages.
+    ///
+    /// Only one hook is active per state, setting another one replaces it. The closure must
+    /// not panic, the panic can't unwind through Lua.
+    pub fn set_line_hook<F>(&mut self, f: F)
+        where F: FnMut(&str, u32) + 'static
+    {
+        hooks::set_line_hook(self.state(), f);
+    }
+
+    /// Makes any Lua code running on the state fail with an `interrupted` runtime error once
+    /// `flag` is set, from any thread, so a host can cancel a long script.
+    ///
+    /// The flag is checked every thousand instructions and stays set until the host clears
+    /// it, every call fails meanwhile. This uses the single hook of the state, like
+    /// `set_line_hook`, and replaces it.
+    pub fn set_interrupt(&mut self, flag: Arc<AtomicBool>) {
+        hooks::set_interrupt(self.state(), flag);
+    }
+
+    /// Runs `code` like `exec_string`, but stops it with `LuaError::Timeout` once the wall
+    /// clock reaches `deadline`.
+    ///
+    /// The clock is read every thousand instructions, so time spent inside a single native
+    /// function call isn't interrupted. A hook set with `set_line_hook` is suspended meanwhile.
+    pub fn run_with_deadline(&mut self, code: &str, deadline: Instant) -> Result<(), LuaError> {
+        let state = self.state();
+        let code = CString::new(code).unwrap();
+        let ret = unsafe { td_clua::luaL_loadstring(state, code.as_ptr()) };
+        if ret != 0 {
+            return Err(LuaError::from_status(state, ret));
+        }
+
+        let (ret, expired) = hooks::with_deadline(state, deadline, || unsafe {
+            td_clua::lua_pcall(state, 0, 0, 0)
+        });
+        if ret == 0 {
+            return Ok(());
+        }
+        let err = LuaError::from_status(state, ret);
+        if expired { Err(LuaError::Timeout) } else { Err(err) }
+    }
+
     /// enable hotfix, can update the new func, and the old data will be keep and bind to the new func
     pub fn enable_hotfix(&mut self) {
         hotfix::load_hot_fix(self);
     }
 
+    /// Installs the `u8s` table, whose `len` and `sub` work like `string.len` and `string.sub`
+    /// but count UTF-8 characters instead of bytes.
+    pub fn open_utf8_helpers(&mut self) {
+        utf8::load_utf8_helpers(self);
+    }
+
     pub fn exec_gc(&mut self) -> i32 {
         unsafe { td_clua::lua_gc(self.state(), td_clua::LUA_GCCOLLECT, 0) as i32 } 
     }
 
+    /// Runs incremental garbage collection steps until a collection cycle completes or
+    /// `budget` has elapsed, whichever comes first, to spread the work over frames. Returns
+    /// whether a cycle completed.
+    ///
+    /// The clock is read between steps, so a single step may overrun the budget a little.
+    pub fn gc_collect_for(&mut self, budget: Duration) -> bool {
+        let start = Instant::now();
+        loop {
+            if unsafe { td_clua::lua_gc(self.state(), td_clua::LUA_GCSTEP, 1) } != 0 {
+                return true;
+            }
+            if start.elapsed() >= budget {
+                return false;
+            }
+        }
+    }
+
+    /// Stops the garbage collector until `gc_restart` is called.
+    pub fn gc_stop(&mut self) {
+        unsafe { td_clua::lua_gc(self.state(), td_clua::LUA_GCSTOP, 0) };
+    }
+
+    /// Restarts the garbage collector after `gc_stop`.
+    pub fn gc_restart(&mut self) {
+        unsafe { td_clua::lua_gc(self.state(), td_clua::LUA_GCRESTART, 0) };
+    }
+
+    /// Whether the garbage collector is running, i.e. not stopped.
+    pub fn gc_is_running(&self) -> bool {
+        unsafe { td_clua::lua_gc(self.lua, td_clua::LUA_GCISRUNNING, 0) != 0 }
+    }
+
+    /// Number of values on the stack, which is also the index of the top one.
+    pub fn top(&self) -> i32 {
+        unsafe { td_clua::lua_gettop(self.lua) }
+    }
+
+    /// Whether the value at `index` is nil.
+    pub fn is_nil(&self, index: i32) -> bool {
+        unsafe { td_clua::lua_isnil(self.lua, index) }
+    }
+
+    /// Whether the value at `index` is nil or the index is past the top of the stack, as for
+    /// an optional argument that wasn't given.
+    pub fn is_none_or_nil(&self, index: i32) -> bool {
+        unsafe { td_clua::lua_isnoneornil(self.lua, index) }
+    }
+
+    /// Whether the value at `index` has the type `ty`, without reading it. Numeric strings
+    /// are strings and not numbers here.
+    pub fn check_type(&self, index: i32, ty: LuaType) -> bool {
+        unsafe { td_clua::lua_type(self.lua, index) == ty as i32 }
+    }
+
+    /// Pops `n` values from the top of the stack.
+    ///
+    /// Stack effect: -n.
+    pub fn pop(&mut self, n: i32) {
+        unsafe { td_clua::lua_pop(self.lua, n) }
+    }
+
+    /// Pushes a copy of the value at `index` on the top of the stack.
+    ///
+    /// Stack effect: +1.
+    pub fn copy_value(&mut self, index: i32) {
+        unsafe { td_clua::lua_pushvalue(self.state(), index) }
+    }
+
+    /// Pops the value on the top of the stack and stores it at `index`, replacing what was there.
+    ///
+    /// Stack effect: -1.
+    pub fn replace(&mut self, index: i32) {
+        unsafe { td_clua::lua_replace(self.state(), index) }
+    }
+
+    /// Pops the top `n` values and pushes their concatenation, following the rules of the `..`
+    /// operator: numbers are converted and `__concat` is called for other values. With `n` 0
+    /// an empty string is pushed.
+    ///
+    /// Values that can't be concatenated raise a Lua error, see `protected`.
+    ///
+    /// Stack effect: -n+1.
+    pub fn concat(&mut self, n: i32) {
+        unsafe { td_clua::lua_concat(self.state(), n) }
+    }
+
+    /// Reads the top `count` values of the stack, the deepest first, then pops them.
+    ///
+    /// `T` is usually a tuple with one element per value, the values are popped even when
+    /// reading fails.
+    ///
+    /// Stack effect: -count.
+    pub fn read_results<T>(&mut self, count: i32) -> Option<T>
+        where T: LuaRead
+    {
+        let _guard = LuaGuard::new(self.lua, count);
+        LuaRead::lua_read_at_position(self.lua, -count)
+    }
+
+    /// Number of arguments received by the running native function, which is the size of
+    /// its stack.
+    pub fn arg_count(&self) -> i32 {
+        unsafe { td_clua::lua_gettop(self.lua) }
+    }
+
+    /// Tells an omitted argument `n` of the running native function from an explicit nil,
+    /// both of which read as nil, e.g. to leave a setting unchanged rather than clear it.
+    pub fn arg_presence(&self, n: i32) -> ArgPresence {
+        if n < 1 || n > self.arg_count() {
+            return ArgPresence::Absent;
+        }
+        match unsafe { td_clua::lua_type(self.lua, n) } {
+            td_clua::LUA_TNONE => ArgPresence::Absent,
+            td_clua::LUA_TNIL => ArgPresence::Nil,
+            _ => ArgPresence::Present,
+        }
+    }
+
+    /// Reads the argument `n` of the running native function, counting from 1. Returns
+    /// `None` if there is no such argument or it can't be read as `V`.
+    pub fn arg<V>(&mut self, n: i32) -> Option<V>
+        where V: LuaRead
+    {
+        if n < 1 || n > self.arg_count() {
+            return None;
+        }
+        LuaRead::lua_read_at_position(self.lua, n)
+    }
+
+    /// Reads the argument `n` of the running native function like `arg`, or raises the
+    /// standard argument error naming `fname`, such as
+    /// `bad argument #2 to 'fname' (string expected, got number)`.
+    ///
+    /// As with `raise_error`, the error doesn't return and values alive in the calling frames
+    /// aren't dropped.
+    ///
+    /// The message is built here rather than by `luaL_argerror`, which takes the function
+    /// name from the debug information of the call instead of `fname`.
+    pub fn check_arg<V>(&mut self, n: i32, fname: &str) -> V
+        where V: LuaRead
+    {
+        if let Some(value) = self.arg(n) {
+            return value;
+        }
+        let err_msg = {
+            let got = if n < 1 || n > self.arg_count() {
+                "no value".to_string()
+            } else {
+                let name = unsafe { CStr::from_ptr(td_clua::lua_typename(self.lua, td_clua::lua_type(self.lua, n))) };
+                name.to_string_lossy().into_owned()
+            };
+            format!("bad argument #{} to '{}' ({} expected, got {})", n, fname, V::lua_type_name(), got)
+        };
+        self.raise_error(err_msg)
+    }
+
+    /// Reads the optional argument `n` of the running native function: `default` when it is
+    /// nil or missing, otherwise the value read as with `check_arg`, which raises the standard
+    /// argument error naming `fname` when it has the wrong type.
+    pub fn opt_arg<V>(&mut self, n: i32, fname: &str, default: V) -> V
+        where V: LuaRead
+    {
+        if n < 1 || n > self.arg_count() || unsafe { td_clua::lua_isnil(self.lua, n) } {
+            return default;
+        }
+        drop(default);
+        self.check_arg(n, fname)
+    }
+
+    /// Reads every value from stack index `from` up to the top, as received by a variadic
+    /// native function, stopping at the first one that can't be read as `T`.
+    ///
+    /// Nothing is popped.
+    pub fn read_args<T>(&mut self, from: i32) -> Vec<T>
+        where T: LuaRead
+    {
+        let top = unsafe { td_clua::lua_gettop(self.lua) };
+        let mut args = vec![];
+        for index in from..top + 1 {
+            match LuaRead::lua_read_at_position(self.lua, index) {
+                Some(arg) => args.push(arg),
+                None => break,
+            }
+        }
+        args
+    }
+
     impl_exec_func!(exec_func0, );
     impl_exec_func!(exec_func1, A);
     impl_exec_func!(exec_func2, A, B);
@@ -324,6 +1047,60 @@ pub trait LuaPush {
     fn push_to_lua(self, lua: *mut lua_State) -> i32;
 }
 
+/// Error returned by `LuaTryPush` when a value refuses to be pushed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PushError {
+    pub message: String,
+}
+
+impl PushError {
+    pub fn new<S>(message: S) -> PushError where S: Into<String> {
+        PushError {
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for PushError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "push error: {}", self.message)
+    }
+}
+
+impl Error for PushError {
+}
+
+/// The basic types of Lua values, as given by `lua_type`, see `Lua::check_type`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LuaType {
+    /// No value, the index is past the top of the stack.
+    None = td_clua::LUA_TNONE as isize,
+    Nil = td_clua::LUA_TNIL as isize,
+    Boolean = td_clua::LUA_TBOOLEAN as isize,
+    LightUserdata = td_clua::LUA_TLIGHTUSERDATA as isize,
+    Number = td_clua::LUA_TNUMBER as isize,
+    String = td_clua::LUA_TSTRING as isize,
+    Table = td_clua::LUA_TTABLE as isize,
+    Function = td_clua::LUA_TFUNCTION as isize,
+    Userdata = td_clua::LUA_TUSERDATA as isize,
+    Thread = td_clua::LUA_TTHREAD as isize,
+}
+
+/// Types that may fail validation when given to a Lua context.
+///
+/// Every `LuaPush` type implements it and never fails. Types that validate their content
+/// implement it directly instead of `LuaPush`.
+pub trait LuaTryPush {
+    /// Pushes the value on the top of the stack, or returns an error without pushing anything.
+    fn try_push_to_lua(self, lua: *mut lua_State) -> Result<i32, PushError>;
+}
+
+impl<T> LuaTryPush for T where T: LuaPush {
+    fn try_push_to_lua(self, lua: *mut lua_State) -> Result<i32, PushError> {
+        Ok(self.push_to_lua(lua))
+    }
+}
+
 /// Types that can be obtained from a Lua context.
 ///
 /// Most types that implement `LuaPush` also implement `LuaRead`, but this is not always the case
@@ -341,6 +1118,12 @@ pub trait LuaRead: Sized {
 
     /// Reads the data from Lua at a given position.
     fn lua_read_with_pop(lua: *mut lua_State, index: i32, pop: i32) -> Option<Self>;
+
+    /// The name of the Lua type read, as shown in the messages of argument errors such as
+    /// `string expected, got number`.
+    fn lua_type_name() -> &'static str {
+        "value"
+    }
 }
 
 impl Drop for Lua {
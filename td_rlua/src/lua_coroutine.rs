@@ -0,0 +1,98 @@
+use td_clua::{self, lua_State};
+use LuaError;
+use LuaFunction;
+use LuaPush;
+use LuaRead;
+use lua_function::{bad_result, main_thread, Reference};
+
+/// The state of a coroutine, as given by `coroutine.status`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CoroutineStatus {
+    /// Not started yet or yielded, it can be resumed.
+    Suspended,
+    /// It is the coroutine running the code that asked.
+    Running,
+    /// Active but not running, it resumed another coroutine.
+    Normal,
+    /// Returned or stopped by an error, it can't be resumed anymore.
+    Dead,
+}
+
+/// A Lua thread running a function as a coroutine, kept alive by a reference in the registry.
+///
+/// The handle must be dropped before the state is closed.
+pub struct LuaCoroutine {
+    thread: *mut lua_State,
+    reference: Reference,
+}
+
+impl LuaCoroutine {
+    /// Creates a suspended coroutine that runs `function` when first resumed.
+    pub fn new(function: &LuaFunction) -> LuaCoroutine {
+        // the new thread must outlive whatever coroutine the handle was taken from
+        let lua = main_thread(function.state());
+        let thread = unsafe { td_clua::lua_newthread(lua) };
+        let reference = Reference::from_top(lua);
+        function.clone().push_to_lua(thread);
+        LuaCoroutine { thread, reference }
+    }
+
+    /// The state of the coroutine, seen from outside of it: a coroutine that is active is
+    /// reported as `Normal`, see `status_from`.
+    pub fn status(&self) -> CoroutineStatus {
+        self.status_from(self.reference.lua)
+    }
+
+    /// The state of the coroutine as `coroutine.status` gives it when called from the thread
+    /// `lua`, usually the state received by a native function: `Running` if `lua` is the
+    /// coroutine itself.
+    pub fn status_from(&self, lua: *mut lua_State) -> CoroutineStatus {
+        if lua == self.thread {
+            return CoroutineStatus::Running;
+        }
+        match unsafe { td_clua::lua_status(self.thread) } {
+            td_clua::LUA_YIELD => CoroutineStatus::Suspended,
+            td_clua::LUA_OK => {
+                let mut ar = td_clua::lua_Debug::default();
+                if unsafe { td_clua::lua_getstack(self.thread, 0, &mut ar) } > 0 {
+                    CoroutineStatus::Normal
+                } else if unsafe { td_clua::lua_gettop(self.thread) } == 0 {
+                    CoroutineStatus::Dead
+                } else {
+                    CoroutineStatus::Suspended
+                }
+            }
+            _ => CoroutineStatus::Dead,
+        }
+    }
+
+    /// Resumes the coroutine with `args`, pushed as any `LuaPush` value, and reads what it
+    /// yielded or returned as `R`, a tuple for several values.
+    ///
+    /// An error raised by the coroutine kills it and is returned.
+    pub fn resume<A, R>(&mut self, args: A) -> Result<R, LuaError>
+        where A: LuaPush,
+              R: LuaRead
+    {
+        match self.status() {
+            CoroutineStatus::Suspended => {}
+            CoroutineStatus::Dead => return Err(LuaError::Runtime("cannot resume dead coroutine".to_string())),
+            _ => return Err(LuaError::Runtime("cannot resume non-suspended coroutine".to_string())),
+        }
+        let thread = self.thread;
+        let nargs = args.push_to_lua(thread);
+        let ret = unsafe { td_clua::lua_resume(thread, self.reference.lua, nargs) };
+        if ret != td_clua::LUA_OK && ret != td_clua::LUA_YIELD {
+            return Err(LuaError::from_status(thread, ret));
+        }
+
+        let count = unsafe { td_clua::lua_gettop(thread) };
+        if count == 0 {
+            unsafe { td_clua::lua_pushnil(thread) };
+        }
+        let count = count.max(1);
+        let result = LuaRead::lua_read_at_position(thread, -count).ok_or_else(|| bad_result::<R>(thread, -count));
+        unsafe { td_clua::lua_settop(thread, 0) };
+        result
+    }
+}
@@ -0,0 +1,166 @@
+use std::ffi::{CStr, CString};
+use std::rc::Rc;
+
+use libc;
+
+use td_clua::{self, lua_State};
+use LuaError;
+use LuaPush;
+use LuaRead;
+
+// a registry reference, released when the last handle sharing it is dropped
+pub(crate) struct Reference {
+    pub(crate) lua: *mut lua_State,
+    pub(crate) id: i32,
+}
+
+impl Reference {
+    // takes a reference to the value on the top of the stack, popping it; the reference is
+    // anchored on the main thread since `lua` may be a coroutine collected before it is dropped
+    pub(crate) fn from_top(lua: *mut lua_State) -> Reference {
+        let id = unsafe { td_clua::luaL_ref(lua, td_clua::LUA_REGISTRYINDEX) };
+        Reference { lua: main_thread(lua), id }
+    }
+
+    pub(crate) fn push(&self, lua: *mut lua_State) {
+        unsafe { td_clua::lua_rawgeti(lua, td_clua::LUA_REGISTRYINDEX, self.id as td_clua::lua_Integer) };
+    }
+}
+
+// the main thread of the state `lua` belongs to, which lives as long as the state
+pub(crate) fn main_thread(lua: *mut lua_State) -> *mut lua_State {
+    unsafe {
+        td_clua::lua_rawgeti(lua, td_clua::LUA_REGISTRYINDEX, td_clua::LUA_RIDX_MAINTHREAD as td_clua::lua_Integer);
+        let main = td_clua::lua_tothread(lua, -1);
+        td_clua::lua_pop(lua, 1);
+        main
+    }
+}
+
+impl Drop for Reference {
+    fn drop(&mut self) {
+        unsafe { td_clua::luaL_unref(self.lua, td_clua::LUA_REGISTRYINDEX, self.id) };
+    }
+}
+
+/// A handle to a Lua function, kept alive by a reference in the registry.
+///
+/// Unlike `LuaTable`, the handle doesn't hold anything on the stack and can be stored for as
+/// long as needed. Clones share the same reference. Every handle must be dropped before the
+/// state is closed.
+#[derive(Clone)]
+pub struct LuaFunction {
+    reference: Rc<Reference>,
+}
+
+impl LuaFunction {
+    // takes a reference to the function on the top of the stack, popping it
+    fn from_top(lua: *mut lua_State) -> LuaFunction {
+        LuaFunction {
+            reference: Rc::new(Reference::from_top(lua)),
+        }
+    }
+
+    pub(crate) fn state(&self) -> *mut lua_State {
+        self.reference.lua
+    }
+
+    /// Calls the function with `args`, pushed as any `LuaPush` value so a tuple gives several
+    /// arguments, and reads its first result as `R`.
+    ///
+    /// A result that can't be read as `R` is reported as a `LuaError::Runtime` error.
+    pub fn call<A, R>(&self, args: A) -> Result<R, LuaError>
+        where A: LuaPush,
+              R: LuaRead
+    {
+        let lua = self.reference.lua;
+        self.reference.push(lua);
+        let nargs = args.push_to_lua(lua);
+        let ret = unsafe { td_clua::lua_pcall(lua, nargs, 1, 0) };
+        if ret != 0 {
+            return Err(LuaError::from_status(lua, ret));
+        }
+
+        let result = LuaRead::lua_read(lua).ok_or_else(|| bad_result::<R>(lua, -1));
+        unsafe { td_clua::lua_pop(lua, 1) };
+        result
+    }
+}
+
+// the error for a result at `index` that can't be read as `R`
+pub(crate) fn bad_result<R>(lua: *mut lua_State, index: i32) -> LuaError
+    where R: LuaRead
+{
+    let got = unsafe { CStr::from_ptr(td_clua::lua_typename(lua, td_clua::lua_type(lua, index))) };
+    LuaError::Runtime(format!("bad result ({} expected, got {})", R::lua_type_name(), got.to_string_lossy()))
+}
+
+/// Pushes the function the handle refers to.
+impl LuaPush for LuaFunction {
+    fn push_to_lua(self, lua: *mut lua_State) -> i32 {
+        self.reference.push(lua);
+        1
+    }
+}
+
+/// Takes a new reference to the function, C functions included.
+impl LuaRead for LuaFunction {
+    fn lua_read_with_pop(lua: *mut lua_State, index: i32, _pop: i32) -> Option<LuaFunction> {
+        if !unsafe { td_clua::lua_isfunction(lua, index) } {
+            return None;
+        }
+        unsafe { td_clua::lua_pushvalue(lua, index) };
+        Some(LuaFunction::from_top(lua))
+    }
+
+    fn lua_type_name() -> &'static str {
+        "function"
+    }
+}
+
+/// A compiled chunk of Lua code that can be recompiled in place.
+///
+/// The handles given by `function` keep calling the chunk through the same registry
+/// reference, so after `reload` they run the new code.
+pub struct LuaChunk {
+    function: LuaFunction,
+    name: CString,
+}
+
+impl LuaChunk {
+    /// Compiles `code` as a chunk called `chunk_name`, without running it.
+    pub fn load(lua: *mut lua_State, code: &str, chunk_name: &str) -> Result<LuaChunk, LuaError> {
+        let name = CString::new(format!("={}", chunk_name)).unwrap();
+        compile(lua, code, &name)?;
+        Ok(LuaChunk {
+            function: LuaFunction::from_top(lua),
+            name,
+        })
+    }
+
+    /// A handle calling the chunk, which follows it across reloads.
+    pub fn function(&self) -> LuaFunction {
+        self.function.clone()
+    }
+
+    /// Compiles `code` under the same chunk name and makes it the code of the chunk.
+    ///
+    /// If `code` fails to compile, the error is returned and the chunk keeps its previous code.
+    pub fn reload(&mut self, code: &str) -> Result<(), LuaError> {
+        let reference = &self.function.reference;
+        compile(reference.lua, code, &self.name)?;
+        unsafe { td_clua::lua_rawseti(reference.lua, td_clua::LUA_REGISTRYINDEX, reference.id as td_clua::lua_Integer) };
+        Ok(())
+    }
+}
+
+// pushes the compiled chunk, or pops the error and returns it
+pub(crate) fn compile(lua: *mut lua_State, code: &str, name: &CStr) -> Result<(), LuaError> {
+    let ret = unsafe {
+        td_clua::luaL_loadbuffer(lua, code.as_ptr() as *const libc::c_char, code.len(), name.as_ptr())
+    };
+    if ret != 0 {
+        return Err(LuaError::from_status(lua, ret));
+    }
+    Ok(())
+}
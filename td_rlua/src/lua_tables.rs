@@ -1,4 +1,5 @@
 use std::marker::PhantomData;
+use std::ptr;
 
 use libc;
 
@@ -6,6 +7,130 @@ use td_clua::{self, lua_State};
 use LuaPush;
 use LuaRead;
 use LuaGuard;
+use lua_function::Reference;
+use userdata::push_userdata;
+
+// `__index` installed by `LuaTable::set_default`, the default is the upvalue
+extern "C" fn default_index_wrapper<D>(lua: *mut lua_State) -> libc::c_int
+    where D: LuaPush + Clone
+{
+    let default = unsafe { &*(td_clua::lua_touserdata(lua, td_clua::lua_upvalueindex(1)) as *const D) };
+    default.clone().push_to_lua(lua)
+}
+
+// replaces the table on the top of the stack by its copy from `seen`, making an empty copy
+// and queuing the pair when the table is met for the first time; other values are left as is
+unsafe fn clone_table_ref(lua: *mut lua_State, seen: i32, queue: i32, queued: &mut td_clua::lua_Integer) {
+    if !td_clua::lua_istable(lua, -1) {
+        return;
+    }
+    td_clua::lua_pushvalue(lua, -1);
+    td_clua::lua_rawget(lua, seen);
+    if !td_clua::lua_isnil(lua, -1) {
+        td_clua::lua_replace(lua, -2);
+        return;
+    }
+    td_clua::lua_pop(lua, 1);
+
+    td_clua::lua_newtable(lua);
+    if td_clua::lua_getmetatable(lua, -2) != 0 {
+        td_clua::lua_setmetatable(lua, -2);
+    }
+    td_clua::lua_pushvalue(lua, -2);
+    td_clua::lua_pushvalue(lua, -2);
+    td_clua::lua_rawset(lua, seen);
+    *queued += 1;
+    td_clua::lua_pushvalue(lua, -2);
+    td_clua::lua_rawseti(lua, queue, 2 * *queued - 1);
+    td_clua::lua_pushvalue(lua, -1);
+    td_clua::lua_rawseti(lua, queue, 2 * *queued);
+    td_clua::lua_replace(lua, -2);
+}
+
+// copies every entry of the table at the absolute index `source` into the table at `dest`,
+// the tables reachable from it being copied too, once each and with their metatable; `source`
+// itself maps to `dest`
+unsafe fn deep_copy_into(lua: *mut lua_State, source: i32, dest: i32) {
+    td_clua::lua_checkstack(lua, 10);
+    // the copy of each table already met, keyed by the original
+    td_clua::lua_newtable(lua);
+    let seen = td_clua::lua_gettop(lua);
+    // (original, copy) pairs whose content is left to copy, at 2n-1 and 2n
+    td_clua::lua_newtable(lua);
+    let queue = seen + 1;
+
+    td_clua::lua_pushvalue(lua, source);
+    td_clua::lua_pushvalue(lua, dest);
+    td_clua::lua_rawset(lua, seen);
+    td_clua::lua_pushvalue(lua, source);
+    td_clua::lua_rawseti(lua, queue, 1);
+    td_clua::lua_pushvalue(lua, dest);
+    td_clua::lua_rawseti(lua, queue, 2);
+    let mut queued = 1;
+
+    let mut next = 0;
+    while next < queued {
+        next += 1;
+        td_clua::lua_rawgeti(lua, queue, 2 * next - 1);
+        td_clua::lua_rawgeti(lua, queue, 2 * next);
+        let (from, to) = (queue + 1, queue + 2);
+        td_clua::lua_pushnil(lua);
+        while td_clua::lua_next(lua, from) != 0 {
+            td_clua::lua_pushvalue(lua, -2);
+            clone_table_ref(lua, seen, queue, &mut queued);
+            td_clua::lua_pushvalue(lua, -2);
+            clone_table_ref(lua, seen, queue, &mut queued);
+            td_clua::lua_rawset(lua, to);
+            // removing the value, leaving only the key on the top of the stack
+            td_clua::lua_pop(lua, 1);
+        }
+        td_clua::lua_pop(lua, 2);
+    }
+    td_clua::lua_pop(lua, 2);
+}
+
+// queues merging the table on the top of the stack into the table below it, unless this pair
+// was already queued, and pops both; `seen` maps each source to the set of its destinations
+unsafe fn queue_merge(lua: *mut lua_State, seen: i32, queue: i32, queued: &mut td_clua::lua_Integer) {
+    td_clua::lua_pushvalue(lua, -1);
+    td_clua::lua_rawget(lua, seen);
+    if td_clua::lua_isnil(lua, -1) {
+        td_clua::lua_pop(lua, 1);
+        td_clua::lua_newtable(lua);
+        td_clua::lua_pushvalue(lua, -2);
+        td_clua::lua_pushvalue(lua, -2);
+        td_clua::lua_rawset(lua, seen);
+    }
+    td_clua::lua_pushvalue(lua, -3);
+    td_clua::lua_rawget(lua, -2);
+    let done = !td_clua::lua_isnil(lua, -1);
+    td_clua::lua_pop(lua, 1);
+    if done {
+        td_clua::lua_pop(lua, 3);
+        return;
+    }
+    td_clua::lua_pushvalue(lua, -3);
+    td_clua::lua_pushboolean(lua, 1);
+    td_clua::lua_rawset(lua, -3);
+    td_clua::lua_pop(lua, 1);
+
+    *queued += 1;
+    td_clua::lua_rawseti(lua, queue, 2 * *queued);
+    td_clua::lua_rawseti(lua, queue, 2 * *queued - 1);
+}
+
+// sets the stack back to `top` when dropped, unwinding included
+struct StackRestore {
+    lua: *mut lua_State,
+    top: i32,
+}
+
+impl Drop for StackRestore {
+    fn drop(&mut self) {
+        unsafe { td_clua::lua_settop(self.lua, self.top) };
+    }
+}
+
 /// Represents a table stored in the Lua context.
 ///
 /// Loading this type mutably borrows the Lua context.
@@ -26,6 +151,10 @@ impl LuaRead for LuaTable {
             None
         }
     }
+
+    fn lua_type_name() -> &'static str {
+        "table"
+    }
 }
 
 impl Drop for LuaTable {
@@ -45,7 +174,32 @@ pub struct LuaTableIterator<'t, K, V> {
     marker: PhantomData<(K, V)>,
 }
 
+/// Iterator over the array part of a Lua table following the `ipairs` contract, see
+/// `LuaTable::ipairs`.
+pub struct LuaTableIpairs<'t, V> {
+    table: &'t mut LuaTable,
+    index: i32,     // absolute stack index of the table
+    next: usize,
+    marker: PhantomData<V>,
+}
+
 impl LuaTable {
+    // pushes a new table with room for `narr` array elements and `nrec` other entries, popped
+    // when the LuaTable is dropped
+    pub(crate) fn push_sized(lua: *mut lua_State, narr: usize, nrec: usize) -> LuaTable {
+        unsafe { td_clua::lua_createtable(lua, narr.min(i32::MAX as usize) as i32, nrec.min(i32::MAX as usize) as i32) };
+        LuaTable {
+            table: lua,
+            pop: 1,
+            index: -1,
+        }
+    }
+
+    // the absolute stack index of the table, which stays valid while values are pushed
+    pub(crate) fn abs_index(&self) -> i32 {
+        unsafe { td_clua::lua_absindex(self.table, self.index) }
+    }
+
     /// Destroys the LuaTable and returns its inner Lua context. Useful when it takes Lua by value.
     pub fn into_inner(self) -> *mut lua_State {
         self.table
@@ -73,6 +227,56 @@ impl LuaTable {
         LuaRead::lua_read_with_pop(self.table, -1, 1)
     }
 
+    /// Loads the value at the end of a dot-separated `path` of string keys, such as
+    /// `"server.tls.enabled"`, walking down from this table.
+    ///
+    /// Returns `None` if a step along the way isn't a table or the value can't be read as `R`.
+    /// Metamethods are called as with `query`.
+    pub fn get_path<R>(&mut self, path: &str) -> Option<R>
+                       where R: LuaRead
+    {
+        let state = self.table;
+        unsafe { td_clua::lua_pushvalue(state, self.index) };
+        for key in path.split('.') {
+            if !unsafe { td_clua::lua_istable(state, -1) } {
+                unsafe { td_clua::lua_pop(state, 1) };
+                return None;
+            }
+            key.push_to_lua(state);
+            unsafe {
+                td_clua::lua_gettable(state, -2);
+                td_clua::lua_remove(state, -2);
+            }
+        }
+        let _guard = LuaGuard::new(state, 1);
+        LuaRead::lua_read_with_pop(state, -1, 1)
+    }
+
+    /// Loads a value in the table given its index, or `R::default()` if the value is nil.
+    ///
+    /// Returns `None` when the value is present but can't be read as `R`.
+    pub fn query_or_default<R, I>(&mut self, index: I) -> Option<R>
+                         where R: LuaRead + Default,
+                               I: LuaPush
+    {
+        index.push_to_lua(self.table);
+        unsafe { td_clua::lua_gettable(self.table, if self.index > 0 { self.index } else {self.index - 1}); }
+        let _guard = LuaGuard::new(self.table, 1);
+        if unsafe { td_clua::lua_isnil(self.table, -1) } {
+            return Some(R::default());
+        }
+        LuaRead::lua_read_with_pop(self.table, -1, 1)
+    }
+
+    /// Loads a value in the table given its index, or returns `default` if the value is
+    /// missing or can't be read as `R`.
+    pub fn query_or<R, I>(&mut self, index: I, default: R) -> R
+                         where R: LuaRead,
+                               I: LuaPush
+    {
+        self.query(index).unwrap_or(default)
+    }
+
     /// Inserts or modifies an elements of the table.
     pub fn set<I, V>(&mut self, index: I, value: V)
                          where I: LuaPush,
@@ -107,12 +311,224 @@ impl LuaTable {
         self.query(index).unwrap()
     }
 
+    /// Appends each element of `iter` after the current end of the array part,
+    /// starting at index `table_len() + 1`.
+    pub fn extend_array<V, T>(&mut self, iter: T)
+                         where V: LuaPush,
+                               T: IntoIterator<Item = V>
+    {
+        let mut index = self.table_len();
+        for value in iter {
+            index += 1;
+            self.set(index, value);
+        }
+    }
+
+    /// Iterates over `t[1]`, `t[2]`, ... with their indices, stopping at the first nil or at
+    /// the first value that can't be read as `V`. Metamethods aren't called.
+    pub fn ipairs<V>(&mut self) -> LuaTableIpairs<'_, V> {
+        let index = unsafe { td_clua::lua_absindex(self.table, self.index) };
+        LuaTableIpairs {
+            table: self,
+            index,
+            next: 1,
+            marker: PhantomData,
+        }
+    }
+
+    /// Calls `f` with every key/value pair of the table that can be read as `(K, V)`, skipping
+    /// the others.
+    ///
+    /// The keys are read from a copy, so reading numbers as `String` doesn't upset the
+    /// traversal. The stack is restored even if `f` panics.
+    pub fn for_each<K, V, F>(&mut self, mut f: F)
+                             where K: LuaRead,
+                                   V: LuaRead,
+                                   F: FnMut(K, V)
+    {
+        let state = self.table;
+        let index = unsafe { td_clua::lua_absindex(state, self.index) };
+        let _restore = StackRestore {
+            lua: state,
+            top: unsafe { td_clua::lua_gettop(state) },
+        };
+
+        unsafe { td_clua::lua_pushnil(state) };
+        while unsafe { td_clua::lua_next(state, index) } != 0 {
+            unsafe { td_clua::lua_pushvalue(state, -2) };
+            let key = LuaRead::lua_read_at_position(state, -1);
+            let value = LuaRead::lua_read_at_position(state, -2);
+            // leaving only the key on the top of the stack for lua_next
+            unsafe { td_clua::lua_pop(state, 2) };
+            if let (Some(key), Some(value)) = (key, value) {
+                f(key, value);
+            }
+        }
+    }
+
+    /// Sorts the keys of the table into integer keys and string keys, in traversal order, to
+    /// tell an array from a map or a mix of both. Keys of other types, floats included, are
+    /// left out.
+    pub fn partition_keys(&mut self) -> (Vec<i64>, Vec<String>) {
+        let state = self.table;
+        let index = unsafe { td_clua::lua_absindex(state, self.index) };
+        let _restore = StackRestore {
+            lua: state,
+            top: unsafe { td_clua::lua_gettop(state) },
+        };
+
+        let mut numbers = Vec::new();
+        let mut strings = Vec::new();
+        unsafe { td_clua::lua_pushnil(state) };
+        while unsafe { td_clua::lua_next(state, index) } != 0 {
+            unsafe {
+                if td_clua::lua_isinteger(state, -2) != 0 {
+                    numbers.push(td_clua::lua_tointegerx(state, -2, ptr::null_mut()) as i64);
+                } else if td_clua::lua_type(state, -2) == td_clua::LUA_TSTRING {
+                    if let Some(key) = LuaRead::lua_read_at_position(state, -2) {
+                        strings.push(key);
+                    }
+                }
+                td_clua::lua_pop(state, 1);
+            }
+        }
+        (numbers, strings)
+    }
+
+    /// Stores `value` at index `table_len() + 1`, like `table.insert(t, value)`.
+    ///
+    /// Unlike `table.insert`, the position comes from the raw length, a `__len` metamethod
+    /// isn't called.
+    pub fn append<V>(&mut self, value: V)
+                     where V: LuaPush
+    {
+        let index = self.table_len() + 1;
+        self.set(index, value);
+    }
+
+    /// Removes `t[index]` in constant time by moving the last element of the array part into
+    /// its place, so the order isn't kept. Returns false if `index` is not within
+    /// `1..=table_len()`.
+    ///
+    /// Metamethods aren't called.
+    pub fn swap_remove(&mut self, index: usize) -> bool {
+        let len = self.table_len();
+        if index == 0 || index > len {
+            return false;
+        }
+        let state = self.table;
+        let table = unsafe { td_clua::lua_absindex(state, self.index) };
+        unsafe {
+            if index != len {
+                td_clua::lua_rawgeti(state, table, len as td_clua::lua_Integer);
+                td_clua::lua_rawseti(state, table, index as td_clua::lua_Integer);
+            }
+            td_clua::lua_pushnil(state);
+            td_clua::lua_rawseti(state, table, len as td_clua::lua_Integer);
+        }
+        true
+    }
+
     pub fn table_len(&mut self) -> usize {
         unsafe {
             td_clua::lua_rawlen(self.table, self.index)
         }
     }
 
+    /// Makes reading any key missing from the table give a clone of `default` instead of nil,
+    /// through an `__index` metamethod.
+    pub fn set_default<D>(&mut self, default: D)
+                          where D: LuaPush + Clone + 'static
+    {
+        let _meta = self.get_or_create_metatable();
+        unsafe {
+            "__index".push_to_lua(self.table);
+            push_userdata(default, self.table, |_| {});
+            td_clua::lua_pushcclosure(self.table, default_index_wrapper::<D>, 1);
+            td_clua::lua_settable(self.table, -3);
+        }
+    }
+
+    /// Pushes a copy of the table in which every table reachable from it is copied too, then
+    /// loads it.
+    ///
+    /// Strings, numbers and booleans are copied. Functions, userdata and threads are shared by
+    /// reference, as are the metatables. A table reached several times, through a cycle
+    /// included, is copied once, so the copy keeps the shape of the original. The tables are
+    /// copied one after the other rather than recursively, any depth can be cloned.
+    /// Metamethods aren't called.
+    pub fn deep_clone(&mut self) -> LuaTable {
+        let state = self.table;
+        let source = unsafe { td_clua::lua_absindex(state, self.index) };
+        unsafe {
+            td_clua::lua_newtable(state);
+            if td_clua::lua_getmetatable(state, source) != 0 {
+                td_clua::lua_setmetatable(state, -2);
+            }
+            deep_copy_into(state, source, td_clua::lua_gettop(state));
+        }
+
+        LuaTable {
+            table: state,
+            pop: 1,
+            index: -1,
+        }
+    }
+
+    /// Copies every entry of `other` into the table, overwriting the keys both have, as when
+    /// laying user settings over defaults.
+    ///
+    /// With `deep`, an entry holding a table in both tables is merged the same way instead of
+    /// replaced, at any depth; a pair of tables met again, through a cycle included, is merged
+    /// once. Otherwise, and for the tables of `other` landing on other values, the tables are
+    /// stored by reference rather than copied. Metamethods aren't called.
+    pub fn merge(&mut self, other: &mut LuaTable, deep: bool) {
+        let state = self.table;
+        let target = unsafe { td_clua::lua_absindex(state, self.index) };
+        let source = unsafe { td_clua::lua_absindex(other.table, other.index) };
+        unsafe {
+            td_clua::lua_checkstack(state, 10);
+            td_clua::lua_newtable(state);
+            let seen = td_clua::lua_gettop(state);
+            // (destination, source) pairs left to merge, at 2n-1 and 2n
+            td_clua::lua_newtable(state);
+            let queue = seen + 1;
+            let mut queued = 0;
+
+            td_clua::lua_pushvalue(state, target);
+            td_clua::lua_pushvalue(state, source);
+            queue_merge(state, seen, queue, &mut queued);
+
+            let mut next = 0;
+            while next < queued {
+                next += 1;
+                td_clua::lua_rawgeti(state, queue, 2 * next - 1);
+                td_clua::lua_rawgeti(state, queue, 2 * next);
+                let (to, from) = (queue + 1, queue + 2);
+                td_clua::lua_pushnil(state);
+                while td_clua::lua_next(state, from) != 0 {
+                    if deep && td_clua::lua_istable(state, -1) {
+                        td_clua::lua_pushvalue(state, -2);
+                        td_clua::lua_rawget(state, to);
+                        if td_clua::lua_istable(state, -1) {
+                            td_clua::lua_pushvalue(state, -2);
+                            queue_merge(state, seen, queue, &mut queued);
+                            td_clua::lua_pop(state, 1);
+                            continue;
+                        }
+                        td_clua::lua_pop(state, 1);
+                    }
+                    td_clua::lua_pushvalue(state, -2);
+                    td_clua::lua_insert(state, -2);
+                    // leaving only the key on the top of the stack for lua_next
+                    td_clua::lua_rawset(state, to);
+                }
+                td_clua::lua_pop(state, 2);
+            }
+            td_clua::lua_pop(state, 2);
+        }
+    }
+
     // /// Obtains or create the metatable of the table.
     pub fn get_or_create_metatable(&mut self) -> LuaTable {
         let result = unsafe { td_clua::lua_getmetatable(self.table, self.index) };
@@ -171,3 +587,133 @@ impl<'t, K, V> Drop for LuaTableIterator<'t, K, V> {
         }
     }
 }
+
+impl<'t, V> Iterator for LuaTableIpairs<'t, V>
+                  where V: LuaRead
+{
+    type Item = (usize, V);
+
+    fn next(&mut self) -> Option<(usize, V)> {
+        if self.next == 0 {
+            return None;
+        }
+        let state = self.table.table;
+        unsafe { td_clua::lua_rawgeti(state, self.index, self.next as td_clua::lua_Integer) };
+        let value = if unsafe { td_clua::lua_isnil(state, -1) } {
+            None
+        } else {
+            LuaRead::lua_read_at_position(state, -1)
+        };
+        unsafe { td_clua::lua_pop(state, 1) };
+
+        match value {
+            Some(value) => {
+                self.next += 1;
+                Some((self.next - 1, value))
+            }
+            None => {
+                // done, 0 is never a valid index
+                self.next = 0;
+                None
+            }
+        }
+    }
+}
+
+/// The content of the global table and of every table reachable from it, taken by
+/// `Lua::snapshot_globals` and kept in the registry.
+///
+/// The snapshot must be dropped before the state is closed.
+pub struct GlobalsSnapshot {
+    // (table, shallow copy of its entries) pairs, at 2n-1 and 2n, the global table first
+    tables: Reference,
+}
+
+impl GlobalsSnapshot {
+    pub(crate) fn take(lua: *mut lua_State) -> GlobalsSnapshot {
+        unsafe {
+            td_clua::lua_checkstack(lua, 10);
+            // the tables already met
+            td_clua::lua_newtable(lua);
+            let seen = td_clua::lua_gettop(lua);
+            td_clua::lua_newtable(lua);
+            let tables = seen + 1;
+
+            td_clua::lua_pushglobaltable(lua);
+            td_clua::lua_pushvalue(lua, -1);
+            td_clua::lua_pushboolean(lua, 1);
+            td_clua::lua_rawset(lua, seen);
+            td_clua::lua_rawseti(lua, tables, 1);
+            let mut queued: td_clua::lua_Integer = 1;
+
+            // the tables met while copying one are queued in turn, metatables aren't followed
+            let mut next = 0;
+            while next < queued {
+                next += 1;
+                td_clua::lua_rawgeti(lua, tables, 2 * next - 1);
+                let from = tables + 1;
+                td_clua::lua_newtable(lua);
+                td_clua::lua_pushnil(lua);
+                while td_clua::lua_next(lua, from) != 0 {
+                    for index in &[-2, -1] {
+                        if !td_clua::lua_istable(lua, *index) {
+                            continue;
+                        }
+                        td_clua::lua_pushvalue(lua, *index);
+                        td_clua::lua_rawget(lua, seen);
+                        let met = !td_clua::lua_isnil(lua, -1);
+                        td_clua::lua_pop(lua, 1);
+                        if !met {
+                            td_clua::lua_pushvalue(lua, *index);
+                            td_clua::lua_pushboolean(lua, 1);
+                            td_clua::lua_rawset(lua, seen);
+                            queued += 1;
+                            td_clua::lua_pushvalue(lua, *index);
+                            td_clua::lua_rawseti(lua, tables, 2 * queued - 1);
+                        }
+                    }
+                    td_clua::lua_pushvalue(lua, -2);
+                    td_clua::lua_insert(lua, -2);
+                    td_clua::lua_rawset(lua, from + 1);
+                }
+                td_clua::lua_rawseti(lua, tables, 2 * next);
+                td_clua::lua_pop(lua, 1);
+            }
+        }
+        let tables = Reference::from_top(lua);
+        unsafe { td_clua::lua_pop(lua, 1) };
+        GlobalsSnapshot { tables }
+    }
+
+    // clears each table of the snapshot, then refills it with its saved entries, so the
+    // tables keep their identity
+    pub(crate) fn restore(&self, lua: *mut lua_State) {
+        unsafe {
+            td_clua::lua_checkstack(lua, 6);
+            self.tables.push(lua);
+            let tables = td_clua::lua_gettop(lua);
+            let count = td_clua::lua_rawlen(lua, tables) as td_clua::lua_Integer / 2;
+            for n in 1..count + 1 {
+                td_clua::lua_rawgeti(lua, tables, 2 * n - 1);
+                let table = tables + 1;
+                td_clua::lua_pushnil(lua);
+                while td_clua::lua_next(lua, table) != 0 {
+                    // clearing a field during the traversal is allowed
+                    td_clua::lua_pop(lua, 1);
+                    td_clua::lua_pushvalue(lua, -1);
+                    td_clua::lua_pushnil(lua);
+                    td_clua::lua_rawset(lua, table);
+                }
+                td_clua::lua_rawgeti(lua, tables, 2 * n);
+                td_clua::lua_pushnil(lua);
+                while td_clua::lua_next(lua, table + 1) != 0 {
+                    td_clua::lua_pushvalue(lua, -2);
+                    td_clua::lua_insert(lua, -2);
+                    td_clua::lua_rawset(lua, table);
+                }
+                td_clua::lua_pop(lua, 2);
+            }
+            td_clua::lua_pop(lua, 1);
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use std::collections::HashSet;
+use std::ffi::CString;
+use std::ptr;
+
+use libc;
+
+use td_clua::{self, lua_State};
+use LuaPush;
+use LuaRead;
+use PushError;
+
+const MAX_DEPTH_KEY: &str = "__td_rlua_max_table_depth";
+
+/// How deeply tables may nest when a `LuaValue` is pushed or read, unless changed with
+/// `Lua::set_max_table_depth`.
+pub const DEFAULT_MAX_TABLE_DEPTH: usize = 200;
+
+pub fn set_max_table_depth(lua: *mut lua_State, depth: usize) {
+    let key = CString::new(MAX_DEPTH_KEY).unwrap();
+    unsafe {
+        td_clua::lua_pushinteger(lua, depth.min(isize::MAX as usize) as td_clua::lua_Integer);
+        td_clua::lua_setfield(lua, td_clua::LUA_REGISTRYINDEX, key.as_ptr());
+    }
+}
+
+pub fn max_table_depth(lua: *mut lua_State) -> usize {
+    let key = CString::new(MAX_DEPTH_KEY).unwrap();
+    unsafe {
+        td_clua::lua_getfield(lua, td_clua::LUA_REGISTRYINDEX, key.as_ptr());
+        let depth = if td_clua::lua_isinteger(lua, -1) != 0 {
+            td_clua::lua_tointegerx(lua, -1, ptr::null_mut()) as usize
+        } else {
+            DEFAULT_MAX_TABLE_DEPTH
+        };
+        td_clua::lua_pop(lua, 1);
+        depth
+    }
+}
+
+/// A dynamically typed copy of a Lua value.
+///
+/// Functions, userdata and threads have no owned representation; table entries holding them
+/// are skipped when reading.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LuaValue {
+    Nil,
+    Boolean(bool),
+    Integer(i64),
+    Number(f64),
+    String(String),
+    /// The key/value pairs of a table, in `lua_next` order.
+    Table(Vec<(LuaValue, LuaValue)>),
+    /// A table that is already being read higher up the tree, left in place of the
+    /// reference so that self-referential tables don't recurse forever.
+    Cycle,
+}
+
+// set when a table nested deeper than the state allows is met, failing the whole read
+struct TooDeep;
+
+// the tables being read on the way down to the current value
+struct Visiting {
+    tables: HashSet<*const libc::c_void>,
+    max_depth: usize,
+}
+
+// reads the value at the absolute index `index`
+fn read_value(lua: *mut lua_State, index: i32, visiting: &mut Visiting) -> Result<Option<LuaValue>, TooDeep> {
+    Ok(match unsafe { td_clua::lua_type(lua, index) } {
+        td_clua::LUA_TNIL => Some(LuaValue::Nil),
+        td_clua::LUA_TBOOLEAN => Some(LuaValue::Boolean(unsafe { td_clua::lua_toboolean(lua, index) } != 0)),
+        td_clua::LUA_TNUMBER => {
+            if unsafe { td_clua::lua_isinteger(lua, index) } != 0 {
+                i64::lua_read_at_position(lua, index).map(LuaValue::Integer)
+            } else {
+                f64::lua_read_at_position(lua, index).map(LuaValue::Number)
+            }
+        }
+        td_clua::LUA_TSTRING => String::lua_read_at_position(lua, index).map(LuaValue::String),
+        td_clua::LUA_TTABLE => {
+            let ptr = unsafe { td_clua::lua_topointer(lua, index) };
+            if visiting.tables.contains(&ptr) {
+                return Ok(Some(LuaValue::Cycle));
+            }
+            if visiting.tables.len() >= visiting.max_depth {
+                return Err(TooDeep);
+            }
+            visiting.tables.insert(ptr);
+            let table = read_table(lua, index, visiting);
+            visiting.tables.remove(&ptr);
+            return table;
+        }
+        _ => None,
+    })
+}
+
+fn read_table(lua: *mut lua_State, index: i32, visiting: &mut Visiting) -> Result<Option<LuaValue>, TooDeep> {
+    if unsafe { td_clua::lua_checkstack(lua, 2) } == 0 {
+        return Err(TooDeep);
+    }
+    let mut pairs = vec![];
+    unsafe { td_clua::lua_pushnil(lua) };
+    while unsafe { td_clua::lua_next(lua, index) } != 0 {
+        let top = unsafe { td_clua::lua_gettop(lua) };
+        let pair = read_value(lua, top - 1, visiting)
+            .and_then(|key| read_value(lua, top, visiting).map(|value| (key, value)));
+        let pair = match pair {
+            Ok(pair) => pair,
+            Err(err) => {
+                // dropping the key and value, lua_next won't be called again
+                unsafe { td_clua::lua_pop(lua, 2) };
+                return Err(err);
+            }
+        };
+        // removing the value, leaving only the key on the top of the stack
+        unsafe { td_clua::lua_pop(lua, 1) };
+        if let (Some(key), Some(value)) = pair {
+            pairs.push((key, value));
+        }
+    }
+    Ok(Some(LuaValue::Table(pairs)))
+}
+
+// pushes the value without checking its depth, see `LuaValue::try_push`
+fn push_value(value: LuaValue, lua: *mut lua_State) {
+    match value {
+        LuaValue::Nil | LuaValue::Cycle => unsafe { td_clua::lua_pushnil(lua) },
+        LuaValue::Boolean(val) => { val.push_to_lua(lua); }
+        LuaValue::Integer(val) => { val.push_to_lua(lua); }
+        LuaValue::Number(val) => { val.push_to_lua(lua); }
+        LuaValue::String(val) => { val.push_to_lua(lua); }
+        LuaValue::Table(pairs) => unsafe {
+            td_clua::lua_createtable(lua, 0, pairs.len() as i32);
+            for (key, value) in pairs {
+                // nil and NaN keys can't be stored
+                match key {
+                    LuaValue::Nil | LuaValue::Cycle => continue,
+                    LuaValue::Number(n) if n.is_nan() => continue,
+                    _ => {}
+                }
+                push_value(key, lua);
+                push_value(value, lua);
+                td_clua::lua_rawset(lua, -3);
+            }
+        },
+    }
+}
+
+impl LuaValue {
+    /// Reads the value at `index`, tables included, the same as `LuaRead`.
+    pub fn from_stack(lua: *mut lua_State, index: i32) -> Option<LuaValue> {
+        LuaRead::lua_read_at_position(lua, index)
+    }
+
+    /// How many tables are nested in the value, 0 for anything but a table.
+    ///
+    /// The tree is walked without recursion, so any depth can be measured.
+    pub fn depth(&self) -> usize {
+        let mut max = 0;
+        let mut pending = vec![(self, 0)];
+        while let Some((value, depth)) = pending.pop() {
+            if let LuaValue::Table(ref pairs) = *value {
+                max = max.max(depth + 1);
+                for (key, value) in pairs {
+                    pending.push((key, depth + 1));
+                    pending.push((value, depth + 1));
+                }
+            }
+        }
+        max
+    }
+
+    /// Pushes the value like `LuaPush`, or returns an error without pushing anything when
+    /// its tables nest deeper than `Lua::set_max_table_depth` allows or the Lua stack can't
+    /// grow enough to build them.
+    pub fn try_push(self, lua: *mut lua_State) -> Result<i32, PushError> {
+        let depth = self.depth();
+        let max = max_table_depth(lua);
+        if depth > max {
+            return Err(PushError::new(format!("tables nested {} deep, the limit is {}", depth, max)));
+        }
+        // each level holds its table, a key and a value on the stack
+        if depth > 0 && unsafe { td_clua::lua_checkstack(lua, (depth * 3).min(i32::MAX as usize) as i32) } == 0 {
+            return Err(PushError::new("stack overflow"));
+        }
+        push_value(self, lua);
+        Ok(1)
+    }
+}
+
+/// Fails when the tables nest deeper than `Lua::set_max_table_depth` allows.
+impl LuaRead for LuaValue {
+    fn lua_read_with_pop(lua: *mut lua_State, index: i32, _pop: i32) -> Option<LuaValue> {
+        let index = unsafe { td_clua::lua_absindex(lua, index) };
+        let mut visiting = Visiting {
+            tables: HashSet::new(),
+            max_depth: max_table_depth(lua),
+        };
+        read_value(lua, index, &mut visiting).unwrap_or(None)
+    }
+}
+
+/// Pushes a copy of the value, tables are rebuilt as new tables. `Cycle` pushes nil since the
+/// table it stood for can't be recovered.
+///
+/// A value nesting tables deeper than `Lua::set_max_table_depth` allows is pushed as nil,
+/// `LuaValue::try_push` reports it as an error instead.
+impl LuaPush for LuaValue {
+    fn push_to_lua(self, lua: *mut lua_State) -> i32 {
+        if self.try_push(lua).is_err() {
+            unsafe { td_clua::lua_pushnil(lua) };
+        }
+        1
+    }
+}
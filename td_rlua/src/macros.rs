@@ -0,0 +1,180 @@
+/// Implements `LuaRead` for a struct by reading each listed field from the table entry
+/// of the same name.
+///
+/// Reading fails when the value isn't a table, or when a field is missing or has the wrong
+/// type. Fields marked `#[lua(default)]` take `Default::default()` when absent (nil), but still
+/// fail when present with the wrong type. `#[lua(rename = "key")]` reads the field from `key`
+/// instead of its Rust name.
+///
+/// ```
+/// # #[macro_use] extern crate td_rlua;
+/// struct Config {
+///     host: String,
+///     port: u16,
+///     max_clients: u32,
+/// }
+///
+/// impl_from_lua_table!(Config {
+///     host,
+///     #[lua(default)]
+///     port,
+///     #[lua(rename = "maxClients", default)]
+///     max_clients,
+/// });
+/// # fn main() {}
+/// ```
+#[macro_export]
+macro_rules! impl_from_lua_table {
+    ($name:ident { $($(#[lua($($opt:tt)*)])* $field:ident),* $(,)* }) => (
+        impl $crate::LuaRead for $name {
+            fn lua_read_with_pop(lua: *mut $crate::lua_State, index: i32, _pop: i32) -> Option<$name> {
+                let mut table: $crate::LuaTable = $crate::LuaRead::lua_read_at_position(lua, index)?;
+                Some($name {
+                    $(
+                        $field: $crate::__lua_table_field!(@read table,
+                                                           $crate::__lua_table_field!(@key $field, [$($($opt)* ,)*]),
+                                                           [$($($opt)* ,)*]),
+                    )*
+                })
+            }
+        }
+    );
+}
+
+/// Implements `LuaPush` for a struct by pushing a new table holding each listed field
+/// under its name.
+///
+/// Accepts the same field list as `impl_from_lua_table!`, renamed fields are written under
+/// their Lua name and `#[lua(default)]` has no effect here.
+#[macro_export]
+macro_rules! impl_into_lua_table {
+    ($name:ident { $($(#[lua($($opt:tt)*)])* $field:ident),* $(,)* }) => (
+        impl $crate::LuaPush for $name {
+            fn push_to_lua(self, lua: *mut $crate::lua_State) -> i32 {
+                unsafe { $crate::lua_newtable(lua) };
+                $(
+                    $crate::LuaPush::push_to_lua($crate::__lua_table_field!(@key $field, [$($($opt)* ,)*]), lua);
+                    $crate::LuaPush::push_to_lua(self.$field, lua);
+                    unsafe { $crate::lua_settable(lua, -3) };
+                )*
+                1
+            }
+        }
+    );
+}
+
+/// Implements `LuaRead` for an enum with struct variants from a table whose `tag` entry names
+/// the variant, like serde's internally tagged enums.
+///
+/// The other fields of the variant are read as with `impl_from_lua_table!` and take the same
+/// options. Reading fails when the tag is missing or matches no variant.
+///
+/// ```
+/// # #[macro_use] extern crate td_rlua;
+/// enum Shape {
+///     Circle { radius: f64 },
+///     Rect { width: f64, height: f64 },
+///     Empty,
+/// }
+///
+/// impl_from_lua_tagged!(Shape, tag = "type" {
+///     "circle" => Circle { radius },
+///     "rect" => Rect { width, #[lua(default)] height },
+///     "empty" => Empty {},
+/// });
+/// # fn main() {}
+/// ```
+#[macro_export]
+macro_rules! impl_from_lua_tagged {
+    ($name:ident, tag = $tag:tt {
+        $($variant_tag:tt => $variant:ident { $($(#[lua($($opt:tt)*)])* $field:ident),* $(,)* }),* $(,)*
+    }) => (
+        impl $crate::LuaRead for $name {
+            fn lua_read_with_pop(lua: *mut $crate::lua_State, index: i32, _pop: i32) -> Option<$name> {
+                let mut table: $crate::LuaTable = $crate::LuaRead::lua_read_at_position(lua, index)?;
+                let tag: String = table.query($tag)?;
+                $(
+                    if tag == $variant_tag {
+                        return Some($name::$variant {
+                            $(
+                                $field: $crate::__lua_table_field!(@read table,
+                                                                   $crate::__lua_table_field!(@key $field, [$($($opt)* ,)*]),
+                                                                   [$($($opt)* ,)*]),
+                            )*
+                        });
+                    }
+                )*
+                None
+            }
+        }
+    );
+}
+
+/// Implements `LuaPush` and `LuaRead` for an enum of unit variants by mapping each variant to
+/// a string, which reads better in scripts than a number.
+///
+/// Reading fails for a string matching no variant and for values that aren't strings.
+///
+/// ```
+/// # #[macro_use] extern crate td_rlua;
+/// #[derive(Clone, Copy)]
+/// enum Direction {
+///     North,
+///     South,
+/// }
+///
+/// impl_enum_str!(Direction {
+///     North => "north",
+///     South => "south",
+/// });
+/// # fn main() {}
+/// ```
+#[macro_export]
+macro_rules! impl_enum_str {
+    ($name:ident { $($variant:ident => $text:expr),* $(,)* }) => (
+        impl $crate::LuaPush for $name {
+            fn push_to_lua(self, lua: *mut $crate::lua_State) -> i32 {
+                let text: &str = match self {
+                    $($name::$variant => $text,)*
+                };
+                $crate::LuaPush::push_to_lua(text, lua)
+            }
+        }
+
+        impl $crate::LuaRead for $name {
+            fn lua_read_with_pop(lua: *mut $crate::lua_State, index: i32, _pop: i32) -> Option<$name> {
+                if unsafe { $crate::lua_type(lua, index) } != $crate::LUA_TSTRING {
+                    return None;
+                }
+                let text: String = $crate::LuaRead::lua_read_at_position(lua, index)?;
+                $(
+                    if text == $text {
+                        return Some($name::$variant);
+                    }
+                )*
+                None
+            }
+
+            fn lua_type_name() -> &'static str {
+                "string"
+            }
+        }
+    );
+}
+
+// walks the `#[lua(...)]` options of a single field
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __lua_table_field {
+    (@key $field:ident, []) => (stringify!($field));
+    (@key $field:ident, [, $($rest:tt)*]) => ($crate::__lua_table_field!(@key $field, [$($rest)*]));
+    (@key $field:ident, [rename = $name:tt $($rest:tt)*]) => ($name);
+    (@key $field:ident, [$opt:ident = $v:tt $($rest:tt)*]) => ($crate::__lua_table_field!(@key $field, [$($rest)*]));
+    (@key $field:ident, [$opt:ident $($rest:tt)*]) => ($crate::__lua_table_field!(@key $field, [$($rest)*]));
+
+    (@read $table:ident, $key:expr, []) => ($table.query($key)?);
+    (@read $table:ident, $key:expr, [, $($rest:tt)*]) => ($crate::__lua_table_field!(@read $table, $key, [$($rest)*]));
+    (@read $table:ident, $key:expr, [default $($rest:tt)*]) => ($table.query_or_default($key)?);
+    (@read $table:ident, $key:expr, [$opt:ident = $v:tt $($rest:tt)*]) => ($crate::__lua_table_field!(@read $table, $key, [$($rest)*]));
+    (@read $table:ident, $key:expr, [$opt:ident $($rest:tt)*]) => ($crate::__lua_table_field!(@read $table, $key, [$($rest)*]));
+}
@@ -5,8 +5,9 @@ use LuaPush;
 use LuaRead;
 use LuaTable;
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::hash::Hash;
+use std::ops::{Bound, RangeInclusive};
 
 fn push_iter<V, I>(lua: *mut lua_State, iterator: I) -> i32
                       where V: LuaPush, I: Iterator<Item=V>
@@ -33,6 +34,32 @@ fn push_iter<V, I>(lua: *mut lua_State, iterator: I) -> i32
     1
 }
 
+/// Pushes a new table holding the items of `iterator` at the indices `1..`, pushing and
+/// storing them one at a time as the iterator produces them.
+///
+/// Items that push nothing are skipped, without leaving a hole.
+pub fn push_array<V, I>(lua: *mut lua_State, iterator: I) -> i32
+                        where V: LuaPush, I: IntoIterator<Item=V>
+{
+    let iterator = iterator.into_iter();
+    let (narr, _) = iterator.size_hint();
+    unsafe { td_clua::lua_createtable(lua, narr.min(i32::MAX as usize) as i32, 0) };
+
+    let mut index = 0;
+    for elem in iterator {
+        match elem.push_to_lua(lua) {
+            0 => continue,
+            1 => {
+                index += 1;
+                unsafe { td_clua::lua_rawseti(lua, -2, index) }
+            }
+            size => unsafe { td_clua::lua_pop(lua, size) },
+        }
+    }
+
+    1
+}
+
 fn push_rec_iter<V, I>(lua: *mut lua_State, iterator: I) -> i32
                           where V: LuaPush, I: Iterator<Item=V>
 {
@@ -60,6 +87,12 @@ impl<T> LuaPush for Vec<T> where T: LuaPush {
     }
 }
 
+impl<T> LuaPush for VecDeque<T> where T: LuaPush {
+    fn push_to_lua(self, lua: *mut lua_State) -> i32 {
+        push_iter(lua, self.into_iter())
+    }
+}
+
 impl<'a, T> LuaPush for &'a [T] where T: Clone + LuaPush {
     fn push_to_lua(self, lua: *mut lua_State) -> i32 {
         push_iter(lua, self.iter().map(|e| e.clone()))
@@ -93,4 +126,115 @@ impl<T> LuaRead for Vec<T> where T : LuaRead {
         }
         Some(result)
     }
-}
\ No newline at end of file
+
+    fn lua_type_name() -> &'static str {
+        "table"
+    }
+}
+
+impl<T> LuaRead for VecDeque<T> where T : LuaRead {
+    fn lua_read_with_pop(lua: *mut lua_State, index: i32, _pop : i32) -> Option<VecDeque<T>> {
+        let mut lua_table : LuaTable = unwrap_or!(LuaRead::lua_read_at_position(lua, index), return None);
+        let mut result = VecDeque::new();
+        let len = lua_table.table_len();
+        for i in 1 .. (len + 1) {
+            let val : T = unwrap_or!(lua_table.query(i), return None);
+            result.push_back(val);
+        }
+        Some(result)
+    }
+
+    fn lua_type_name() -> &'static str {
+        "table"
+    }
+}
+
+/// Pushes `{ from = start, to = end, inclusive = true }`, the `inclusive` field telling it
+/// apart from a half-open range.
+impl LuaPush for RangeInclusive<i64> {
+    fn push_to_lua(self, lua: *mut lua_State) -> i32 {
+        let (from, to) = self.into_inner();
+        unsafe { td_clua::lua_createtable(lua, 0, 3) };
+        let mut table : LuaTable = LuaRead::lua_read_at_position(lua, -1).unwrap();
+        table.set("from", from);
+        table.set("to", to);
+        table.set("inclusive", true);
+        1
+    }
+}
+
+/// Reads a table of the shape pushed by `LuaPush`, `inclusive` must be `true`.
+impl LuaRead for RangeInclusive<i64> {
+    fn lua_read_with_pop(lua: *mut lua_State, index: i32, _pop : i32) -> Option<RangeInclusive<i64>> {
+        let mut lua_table : LuaTable = unwrap_or!(LuaRead::lua_read_at_position(lua, index), return None);
+        let from : i64 = unwrap_or!(lua_table.query("from"), return None);
+        let to : i64 = unwrap_or!(lua_table.query("to"), return None);
+        match lua_table.query("inclusive") {
+            Some(true) => Some(from ..= to),
+            _ => None,
+        }
+    }
+
+    fn lua_type_name() -> &'static str {
+        "table"
+    }
+}
+
+/// Pushes `{ kind = "included", value = v }`, `{ kind = "excluded", value = v }` or
+/// `{ kind = "unbounded" }`.
+impl LuaPush for Bound<i64> {
+    fn push_to_lua(self, lua: *mut lua_State) -> i32 {
+        unsafe { td_clua::lua_createtable(lua, 0, 2) };
+        let mut table : LuaTable = LuaRead::lua_read_at_position(lua, -1).unwrap();
+        match self {
+            Bound::Included(value) => {
+                table.set("kind", "included");
+                table.set("value", value);
+            }
+            Bound::Excluded(value) => {
+                table.set("kind", "excluded");
+                table.set("value", value);
+            }
+            Bound::Unbounded => table.set("kind", "unbounded"),
+        }
+        1
+    }
+}
+
+/// Reads a table of the shape pushed by `LuaPush`, `value` is required unless unbounded.
+impl LuaRead for Bound<i64> {
+    fn lua_read_with_pop(lua: *mut lua_State, index: i32, _pop : i32) -> Option<Bound<i64>> {
+        let mut lua_table : LuaTable = unwrap_or!(LuaRead::lua_read_at_position(lua, index), return None);
+        let kind : String = unwrap_or!(lua_table.query("kind"), return None);
+        match &kind[..] {
+            "included" => lua_table.query("value").map(Bound::Included),
+            "excluded" => lua_table.query("value").map(Bound::Excluded),
+            "unbounded" => Some(Bound::Unbounded),
+            _ => None,
+        }
+    }
+
+    fn lua_type_name() -> &'static str {
+        "table"
+    }
+}
+
+/// The key/value pairs of a table, in `lua_next` order.
+///
+/// `Vec<(K, V)>` itself reads the array part of a table as tuples, hence the wrapper. Pairs
+/// that can't be read as `(K, V)` are skipped.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pairs<K, V>(pub Vec<(K, V)>);
+
+impl<K, V> LuaRead for Pairs<K, V> where K: LuaRead, V: LuaRead {
+    fn lua_read_with_pop(lua: *mut lua_State, index: i32, _pop : i32) -> Option<Pairs<K, V>> {
+        let mut lua_table : LuaTable = unwrap_or!(LuaRead::lua_read_at_position(lua, index), return None);
+        let mut pairs = vec![];
+        lua_table.for_each(|key, value| pairs.push((key, value)));
+        Some(Pairs(pairs))
+    }
+
+    fn lua_type_name() -> &'static str {
+        "table"
+    }
+}
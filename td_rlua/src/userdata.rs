@@ -1,9 +1,13 @@
 use std::any::{Any, TypeId};
+use std::borrow::Cow;
 use std::ffi::CString;
+use std::fmt::Debug;
 use std::mem;
 use std::ptr;
 use std::marker::PhantomData;
 use std::boxed::Box;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex, Weak};
 
 use td_clua;
 use td_clua::lua_State;
@@ -12,6 +16,11 @@ use Lua;
 use LuaPush;
 use LuaRead;
 use LuaTable;
+use functions::panic_message;
+
+// holds the values the crate keeps as userdata for its own use, so that they never have the
+// type of a user's value
+pub(crate) struct Internal<T>(pub(crate) T);
 
 // Called when an object inside Lua is being dropped.
 #[inline]
@@ -23,9 +32,9 @@ extern "C" fn destructor_wrapper<T>(lua: *mut td_clua::lua_State) -> libc::c_int
     }
 }
 
-extern "C" fn constructor_wrapper<T>(lua: *mut td_clua::lua_State) -> libc::c_int
+// pushes `t` as a full userdata with the metatable of its struct
+fn push_instance<T>(lua: *mut td_clua::lua_State, t: T)
     where T: NewStruct + Any {
-    let t = T::new();
     let lua_data_raw = unsafe {
         td_clua::lua_newuserdata(lua, mem::size_of::<T>() as libc::size_t)
     };
@@ -37,6 +46,46 @@ extern "C" fn constructor_wrapper<T>(lua: *mut td_clua::lua_State) -> libc::c_in
         td_clua::lua_getglobal(lua, typeid.as_ptr());
         td_clua::lua_setmetatable(lua, -2);
     }
+}
+
+// light version of `push_instance`, the memory is allocated by Rust and never freed by Lua
+fn push_light_instance<T>(lua: *mut td_clua::lua_State, t: T)
+    where T: NewStruct + Any {
+    let t = Box::into_raw(Box::new(t));
+    push_lightuserdata(unsafe { &mut *t }, lua, |_| {});
+    let typeid = CString::new(T::name()).unwrap();
+    unsafe {
+        td_clua::lua_getglobal(lua, typeid.as_ptr());
+        td_clua::lua_setmetatable(lua, -2);
+    }
+}
+
+// pushes an object shared with Rust as a full userdata with the metatable of its struct,
+// see `LuaStruct::register_gc_shared`
+fn push_shared_instance<T>(lua: *mut td_clua::lua_State, data: Arc<Mutex<T>>)
+    where T: NewStruct + Any {
+    let lua_data_raw = unsafe {
+        td_clua::lua_newuserdata(lua, mem::size_of::<Arc<Mutex<T>>>() as libc::size_t)
+    };
+    unsafe {
+        ptr::write(lua_data_raw as *mut Arc<Mutex<T>>, data);
+    }
+    let typeid = CString::new(T::name()).unwrap();
+    unsafe {
+        td_clua::lua_getglobal(lua, typeid.as_ptr());
+        td_clua::lua_setmetatable(lua, -2);
+    }
+}
+
+extern "C" fn constructor_shared_wrapper<T>(lua: *mut td_clua::lua_State) -> libc::c_int
+    where T: NewStruct + Any {
+    push_shared_instance(lua, Arc::new(Mutex::new(T::new())));
+    1
+}
+
+extern "C" fn constructor_wrapper<T>(lua: *mut td_clua::lua_State) -> libc::c_int
+    where T: NewStruct + Any {
+    push_instance(lua, T::new());
     1
 }
 
@@ -45,16 +94,361 @@ extern "C" fn constructor_wrapper<T>(lua: *mut td_clua::lua_State) -> libc::c_in
 // in lua we get the object, we must free the memory
 extern "C" fn constructor_light_wrapper<T>(lua: *mut td_clua::lua_State) -> libc::c_int 
     where T: NewStruct + Any {
-    let t = Box::into_raw(Box::new(T::new()));
-    push_lightuserdata(unsafe { &mut *t }, lua, |_| {});
-    let typeid = CString::new(T::name()).unwrap();
+    push_light_instance(lua, T::new());
+    1
+}
+
+// `clone` method installed by `LuaStruct::register_clone`, the upvalue tells if the struct
+// is light
+extern "C" fn clone_wrapper<T>(lua: *mut td_clua::lua_State) -> libc::c_int
+    where T: NewStruct + Any + Clone {
+    let copy = match read_userdata::<T>(lua, 1) {
+        Some(obj) => obj.clone(),
+        None => {
+            "bad self for method 'clone'".push_to_lua(lua);
+            unsafe {
+                td_clua::lua_error(lua);
+            }
+            unreachable!()
+        }
+    };
+    if unsafe { td_clua::lua_toboolean(lua, td_clua::lua_upvalueindex(1)) } != 0 {
+        push_light_instance(lua, copy);
+    } else {
+        push_instance(lua, copy);
+    }
+    1
+}
+
+// `__eq` installed by `LuaStruct::register_eq_by`
+extern "C" fn eq_wrapper<T>(lua: *mut td_clua::lua_State) -> libc::c_int
+    where T: Any + PartialEq {
+    let a = read_userdata::<T>(lua, 1).map(|a| a as *const T);
+    let b = read_userdata::<T>(lua, 2).map(|b| b as *const T);
+    let equal = match (a, b) {
+        (Some(a), Some(b)) => unsafe { *a == *b },
+        _ => false,
+    };
+    equal.push_to_lua(lua)
+}
+
+// `__tostring` installed by `LuaStruct::register_tostring_debug`
+extern "C" fn tostring_debug_wrapper<T>(lua: *mut td_clua::lua_State) -> libc::c_int
+    where T: Any + Debug {
+    let text = match read_userdata_ref::<T>(lua, 1) {
+        Some(obj) => format!("{:?}", obj),
+        None => format!("userdata: {:p}", unsafe { td_clua::lua_topointer(lua, 1) }),
+    };
+    text.push_to_lua(lua)
+}
+
+// `__newindex` installed by `LuaStruct::forbid_newindex`
+extern "C" fn forbid_newindex_wrapper(lua: *mut td_clua::lua_State) -> libc::c_int {
+    "cannot modify immutable object".push_to_lua(lua);
     unsafe {
-        td_clua::lua_getglobal(lua, typeid.as_ptr());
-        td_clua::lua_setmetatable(lua, -2);
+        td_clua::lua_error(lua);
+    }
+    unreachable!()
+}
+
+// Called when Lua invokes a method registered with `LuaStruct::register_method`,
+// the function pointer and the method name are the upvalues.
+extern "C" fn method_wrapper<T, A, R>(lua: *mut td_clua::lua_State) -> libc::c_int
+    where T: Any,
+          A: LuaRead,
+          R: LuaPush
+{
+    let err_msg = {
+        let method = unsafe { (*(td_clua::lua_touserdata(lua, td_clua::lua_upvalueindex(1)) as *mut Internal<fn(&mut T, A) -> R>)).0 };
+        let name: String = LuaRead::lua_read_at_position(lua, td_clua::lua_upvalueindex(2)).unwrap();
+        match read_userdata::<T>(lua, 1) {
+            Some(obj) => {
+                match LuaRead::lua_read_at_position(lua, 2) {
+                    Some(args) => {
+                        match panic::catch_unwind(AssertUnwindSafe(|| method(obj, args))) {
+                            Ok(ret) => return ret.push_to_lua(lua) as libc::c_int,
+                            Err(payload) => panic_message(lua, payload),
+                        }
+                    }
+                    None => format!("wrong parameter types for method '{}'", name),
+                }
+            }
+            None => format!("bad self for method '{}'", name),
+        }
+    };
+    err_msg.push_to_lua(lua);
+    unsafe {
+        td_clua::lua_error(lua);
+    }
+    unreachable!()
+}
+
+// Called when Lua invokes a method registered with `LuaStruct::register_method_ref`, same
+// upvalues as `method_wrapper` but the object is only borrowed immutably.
+extern "C" fn method_ref_wrapper<T, A, R>(lua: *mut td_clua::lua_State) -> libc::c_int
+    where T: Any,
+          A: LuaRead,
+          R: LuaPush
+{
+    let err_msg = {
+        let method = unsafe { (*(td_clua::lua_touserdata(lua, td_clua::lua_upvalueindex(1)) as *mut Internal<fn(&T, A) -> R>)).0 };
+        let name: String = LuaRead::lua_read_at_position(lua, td_clua::lua_upvalueindex(2)).unwrap();
+        match read_userdata_ref::<T>(lua, 1) {
+            Some(obj) => {
+                match LuaRead::lua_read_at_position(lua, 2) {
+                    Some(args) => {
+                        match panic::catch_unwind(AssertUnwindSafe(|| method(obj, args))) {
+                            Ok(ret) => return ret.push_to_lua(lua) as libc::c_int,
+                            Err(payload) => panic_message(lua, payload),
+                        }
+                    }
+                    None => format!("wrong parameter types for method '{}'", name),
+                }
+            }
+            None => format!("bad self for method '{}'", name),
+        }
+    };
+    err_msg.push_to_lua(lua);
+    unsafe {
+        td_clua::lua_error(lua);
+    }
+    unreachable!()
+}
+
+// `__index` of the instances once `LuaStruct::enable_instance_fields` is called, looks the
+// key up in the methods (upvalue 1), then in the uservalue table of the object
+extern "C" fn instance_index_wrapper(lua: *mut td_clua::lua_State) -> libc::c_int {
+    unsafe {
+        td_clua::lua_pushvalue(lua, 2);
+        td_clua::lua_gettable(lua, td_clua::lua_upvalueindex(1));
+        if !td_clua::lua_isnil(lua, -1) {
+            return 1;
+        }
+        td_clua::lua_pop(lua, 1);
+        if td_clua::lua_getuservalue(lua, 1) != td_clua::LUA_TTABLE {
+            return 0;
+        }
+        td_clua::lua_pushvalue(lua, 2);
+        td_clua::lua_rawget(lua, -2);
+    }
+    1
+}
+
+// `__newindex` of the instances once `LuaStruct::enable_instance_fields` is called, stores
+// the value in the uservalue table of the object, created on the first assignment
+extern "C" fn instance_newindex_wrapper(lua: *mut td_clua::lua_State) -> libc::c_int {
+    unsafe {
+        if td_clua::lua_getuservalue(lua, 1) != td_clua::LUA_TTABLE {
+            td_clua::lua_pop(lua, 1);
+            td_clua::lua_newtable(lua);
+            td_clua::lua_pushvalue(lua, -1);
+            td_clua::lua_setuservalue(lua, 1);
+        }
+        td_clua::lua_pushvalue(lua, 2);
+        td_clua::lua_pushvalue(lua, 3);
+        td_clua::lua_rawset(lua, -3);
+    }
+    0
+}
+
+// Called to read a field registered with `LuaStruct::register_field`, the getter and the
+// field name are the upvalues.
+extern "C" fn field_getter_wrapper<T, R>(lua: *mut td_clua::lua_State) -> libc::c_int
+    where T: Any,
+          R: LuaPush
+{
+    let err_msg = {
+        let getter = unsafe { (*(td_clua::lua_touserdata(lua, td_clua::lua_upvalueindex(1)) as *mut Internal<fn(&T) -> R>)).0 };
+        let name: String = LuaRead::lua_read_at_position(lua, td_clua::lua_upvalueindex(2)).unwrap();
+        match read_userdata::<T>(lua, 1) {
+            Some(obj) => {
+                match panic::catch_unwind(AssertUnwindSafe(|| getter(obj))) {
+                    Ok(ret) => return ret.push_to_lua(lua) as libc::c_int,
+                    Err(payload) => panic_message(lua, payload),
+                }
+            }
+            None => format!("bad self for field '{}'", name),
+        }
+    };
+    err_msg.push_to_lua(lua);
+    unsafe {
+        td_clua::lua_error(lua);
+    }
+    unreachable!()
+}
+
+// Same as `field_getter_wrapper` for a getter of `LuaStruct::register_str_field`, whose
+// result borrows from the object.
+extern "C" fn field_str_getter_wrapper<T>(lua: *mut td_clua::lua_State) -> libc::c_int
+    where T: Any
+{
+    let err_msg = {
+        let getter = unsafe { (*(td_clua::lua_touserdata(lua, td_clua::lua_upvalueindex(1)) as *mut Internal<fn(&T) -> Cow<str>>)).0 };
+        let name: String = LuaRead::lua_read_at_position(lua, td_clua::lua_upvalueindex(2)).unwrap();
+        match read_userdata_ref::<T>(lua, 1) {
+            Some(obj) => {
+                match panic::catch_unwind(AssertUnwindSafe(|| getter(obj))) {
+                    Ok(ret) => return ret.push_to_lua(lua) as libc::c_int,
+                    Err(payload) => panic_message(lua, payload),
+                }
+            }
+            None => format!("bad self for field '{}'", name),
+        }
+    };
+    err_msg.push_to_lua(lua);
+    unsafe {
+        td_clua::lua_error(lua);
+    }
+    unreachable!()
+}
+
+// Called to assign a field registered with `LuaStruct::register_field`, the setter and the
+// field name are the upvalues.
+extern "C" fn field_setter_wrapper<T, W>(lua: *mut td_clua::lua_State) -> libc::c_int
+    where T: Any,
+          W: LuaRead
+{
+    let err_msg = {
+        let setter = unsafe { (*(td_clua::lua_touserdata(lua, td_clua::lua_upvalueindex(1)) as *mut Internal<fn(&mut T, W)>)).0 };
+        let name: String = LuaRead::lua_read_at_position(lua, td_clua::lua_upvalueindex(2)).unwrap();
+        match read_userdata::<T>(lua, 1) {
+            Some(obj) => {
+                match LuaRead::lua_read_at_position(lua, 2) {
+                    Some(value) => {
+                        match panic::catch_unwind(AssertUnwindSafe(|| setter(obj, value))) {
+                            Ok(()) => return 0,
+                            Err(payload) => panic_message(lua, payload),
+                        }
+                    }
+                    None => format!("wrong value type for field '{}'", name),
+                }
+            }
+            None => format!("bad self for field '{}'", name),
+        }
+    };
+    err_msg.push_to_lua(lua);
+    unsafe {
+        td_clua::lua_error(lua);
+    }
+    unreachable!()
+}
+
+// `__index` of the instances once a field is registered, calls the getter of the key found
+// in upvalue 1, or falls back on the previous `__index` (upvalue 2)
+extern "C" fn field_index_wrapper(lua: *mut td_clua::lua_State) -> libc::c_int {
+    unsafe {
+        td_clua::lua_pushvalue(lua, 2);
+        td_clua::lua_rawget(lua, td_clua::lua_upvalueindex(1));
+        if td_clua::lua_isfunction(lua, -1) {
+            td_clua::lua_pushvalue(lua, 1);
+            td_clua::lua_call(lua, 1, 1);
+            return 1;
+        }
+        td_clua::lua_pop(lua, 1);
+
+        match td_clua::lua_type(lua, td_clua::lua_upvalueindex(2)) {
+            td_clua::LUA_TTABLE => {
+                td_clua::lua_pushvalue(lua, 2);
+                td_clua::lua_gettable(lua, td_clua::lua_upvalueindex(2));
+            }
+            td_clua::LUA_TFUNCTION => {
+                td_clua::lua_pushvalue(lua, td_clua::lua_upvalueindex(2));
+                td_clua::lua_pushvalue(lua, 1);
+                td_clua::lua_pushvalue(lua, 2);
+                td_clua::lua_call(lua, 2, 1);
+            }
+            _ => return 0,
+        }
     }
     1
 }
 
+// `__newindex` of the instances once a field is registered, calls the setter of the key
+// found in upvalue 1, or falls back on the previous `__newindex` (upvalue 2)
+extern "C" fn field_newindex_wrapper(lua: *mut td_clua::lua_State) -> libc::c_int {
+    unsafe {
+        td_clua::lua_pushvalue(lua, 2);
+        td_clua::lua_rawget(lua, td_clua::lua_upvalueindex(1));
+        if td_clua::lua_isfunction(lua, -1) {
+            td_clua::lua_pushvalue(lua, 1);
+            td_clua::lua_pushvalue(lua, 3);
+            td_clua::lua_call(lua, 2, 0);
+            return 0;
+        }
+        td_clua::lua_pop(lua, 1);
+
+        match td_clua::lua_type(lua, td_clua::lua_upvalueindex(2)) {
+            td_clua::LUA_TTABLE => {
+                td_clua::lua_pushvalue(lua, 2);
+                td_clua::lua_pushvalue(lua, 3);
+                td_clua::lua_settable(lua, td_clua::lua_upvalueindex(2));
+                return 0;
+            }
+            td_clua::LUA_TFUNCTION => {
+                td_clua::lua_pushvalue(lua, td_clua::lua_upvalueindex(2));
+                td_clua::lua_insert(lua, 1);
+                td_clua::lua_call(lua, 3, 0);
+                return 0;
+            }
+            _ => {}
+        }
+    }
+    let name: Option<String> = LuaRead::lua_read_at_position(lua, 2);
+    let err_msg = match name {
+        Some(name) => format!("no field '{}' to set", name),
+        None => "no field to set".to_string(),
+    };
+    err_msg.push_to_lua(lua);
+    unsafe {
+        td_clua::lua_error(lua);
+    }
+    unreachable!()
+}
+
+// Installed as the `__index` of a struct's method table, called for every name
+// that has no entry there. Returns a closure binding the dispatcher to that name.
+extern "C" fn dispatch_index_wrapper<T, F>(lua: *mut td_clua::lua_State) -> libc::c_int
+    where F: FnMut(&mut T, &str, *mut lua_State) -> Option<i32>,
+          T: Any
+{
+    unsafe {
+        if td_clua::lua_type(lua, 2) != td_clua::LUA_TSTRING {
+            return 0;
+        }
+        td_clua::lua_pushvalue(lua, td_clua::lua_upvalueindex(1));
+        td_clua::lua_pushvalue(lua, 2);
+        td_clua::lua_pushcclosure(lua, dispatch_call_wrapper::<T, F>, 2);
+    }
+    1
+}
+
+// Called when Lua invokes a method resolved by `dispatch_index_wrapper`.
+extern "C" fn dispatch_call_wrapper<T, F>(lua: *mut td_clua::lua_State) -> libc::c_int
+    where F: FnMut(&mut T, &str, *mut lua_State) -> Option<i32>,
+          T: Any
+{
+    let err_msg = {
+        let data_raw = unsafe { td_clua::lua_touserdata(lua, td_clua::lua_upvalueindex(1)) };
+        let dispatcher = unsafe { &mut (*(data_raw as *mut Internal<F>)).0 };
+        let name: String = LuaRead::lua_read_at_position(lua, td_clua::lua_upvalueindex(2)).unwrap();
+        match read_userdata::<T>(lua, 1) {
+            Some(obj) => {
+                match panic::catch_unwind(AssertUnwindSafe(|| dispatcher(obj, &name, lua))) {
+                    Ok(Some(nb)) => return nb as libc::c_int,
+                    Ok(None) => format!("unknown method '{}'", name),
+                    Err(payload) => panic_message(lua, payload),
+                }
+            }
+            None => format!("bad self for method '{}'", name),
+        }
+    };
+    err_msg.push_to_lua(lua);
+    unsafe {
+        td_clua::lua_error(lua);
+    }
+    unreachable!()
+}
+
 /// Pushes an object as a user data.
 ///
 /// In Lua, a user data is anything that is not recognized by Lua. When the script attempts to
@@ -63,52 +457,95 @@ extern "C" fn constructor_light_wrapper<T>(lua: *mut td_clua::lua_State) -> libc
 /// The way a Lua script can use the user data depends on the content of the **metatable**, which
 /// is a Lua table linked to the object.
 ///
+/// `T` doesn't need to be `Send`: types holding an `Rc` or raw pointers can be pushed, since
+/// a state can't leave the thread that owns it (`Lua` and `*mut lua_State` aren't `Send`).
+/// Such a value must still not be handed to another thread through some other channel.
+///
+/// Every push builds a new metatable, see `push_userdata_cached` to share one per type.
+///
 /// # Arguments
 ///
 ///  - `metatable`: Function that fills the metatable of the object.
 ///
 pub fn push_userdata<'a, T, F>(data: T,
                                lua: *mut td_clua::lua_State,
-                               mut metatable: F)
+                               metatable: F)
                                -> i32
     where F: FnMut(LuaTable),
           T: 'a + Any
 {
-    let typeid = format!("{:?}", TypeId::of::<T>());
-    let lua_data_raw = unsafe {
-        td_clua::lua_newuserdata(lua, mem::size_of::<T>() as libc::size_t)
-    };
+    write_userdata(data, lua);
+    unsafe {
+        new_userdata_metatable::<T, F>(lua, metatable);
+        td_clua::lua_setmetatable(lua, -2);
+    }
 
-    // creating a metatable
+    1
+}
+
+/// Same as `push_userdata`, but the metatable is built on the first push of a `T` through
+/// this function and kept in the registry, every later `T` shares it, so
+/// `getmetatable(a) == getmetatable(b)` holds in scripts.
+///
+/// `metatable` is only called when the metatable is built, so every caller pushing a `T`
+/// this way must fill it the same way. Values pushed with `push_userdata` don't use nor
+/// change the shared metatable.
+pub fn push_userdata_cached<'a, T, F>(data: T,
+                                      lua: *mut td_clua::lua_State,
+                                      metatable: F)
+                                      -> i32
+    where F: FnMut(LuaTable),
+          T: 'a + Any
+{
+    write_userdata(data, lua);
+    let key = CString::new(format!("__td_rlua_metatable {:?}", TypeId::of::<T>())).unwrap();
     unsafe {
-        
-        ptr::write(lua_data_raw as *mut _, data);
+        td_clua::lua_getfield(lua, td_clua::LUA_REGISTRYINDEX, key.as_ptr());
+        if !td_clua::lua_istable(lua, -1) {
+            td_clua::lua_pop(lua, 1);
+            new_userdata_metatable::<T, F>(lua, metatable);
+            td_clua::lua_pushvalue(lua, -1);
+            td_clua::lua_setfield(lua, td_clua::LUA_REGISTRYINDEX, key.as_ptr());
+        }
+        td_clua::lua_setmetatable(lua, -2);
+    }
 
-        td_clua::lua_newtable(lua);
+    1
+}
 
-        // index "__typeid" corresponds to the hash of the TypeId of T
-        "__typeid".push_to_lua(lua);
-        typeid.push_to_lua(lua);
-        td_clua::lua_settable(lua, -3);
+// pushes a new full userdata holding `data`, without metatable
+fn write_userdata<T>(data: T, lua: *mut td_clua::lua_State) {
+    unsafe {
+        let lua_data_raw = td_clua::lua_newuserdata(lua, mem::size_of::<T>() as libc::size_t);
+        ptr::write(lua_data_raw as *mut _, data);
+    }
+}
 
-        // index "__gc" call the object's destructor
-        {
-            "__gc".push_to_lua(lua);
+// pushes a new metatable for a userdata holding a `T`, filled by `metatable`
+unsafe fn new_userdata_metatable<T, F>(lua: *mut td_clua::lua_State, mut metatable: F)
+    where F: FnMut(LuaTable),
+          T: Any
+{
+    td_clua::lua_newtable(lua);
 
-            td_clua::lua_pushcfunction(lua, destructor_wrapper::<T>);
+    // index "__typeid" corresponds to the hash of the TypeId of T
+    "__typeid".push_to_lua(lua);
+    format!("{:?}", TypeId::of::<T>()).push_to_lua(lua);
+    td_clua::lua_settable(lua, -3);
 
-            td_clua::lua_settable(lua, -3);
-        }
+    // index "__gc" call the object's destructor
+    {
+        "__gc".push_to_lua(lua);
 
-        // calling the metatable closure
-        {
-            metatable(LuaRead::lua_read(lua).unwrap());
-        }
+        td_clua::lua_pushcfunction(lua, destructor_wrapper::<T>);
 
-        td_clua::lua_setmetatable(lua, -2);
+        td_clua::lua_settable(lua, -3);
     }
 
-    1
+    // calling the metatable closure
+    {
+        metatable(LuaRead::lua_read(lua).unwrap());
+    }
 }
 
 
@@ -184,6 +621,62 @@ pub fn read_userdata<'t, 'c, T>(lua: *mut td_clua::lua_State, index: i32) -> Opt
     }
 }
 
+/// Same as `read_userdata`, but only borrows the object immutably.
+pub fn read_userdata_ref<'t, T>(lua: *mut td_clua::lua_State, index: i32) -> Option<&'t T>
+    where T: 'static + Any
+{
+    read_userdata::<T>(lua, index).map(|obj| &*obj)
+}
+
+/// Pushes a weak handle to an object owned by the Rust side as a user data.
+///
+/// Scripts can keep the handle after the object is destroyed, so methods should get the
+/// object with `read_weak_userdata`, which raises a Lua error once it's gone. The `__gc`
+/// only drops the `Weak` handle.
+pub fn push_weak_userdata<T, F>(weak: Weak<Mutex<T>>,
+                                lua: *mut td_clua::lua_State,
+                                metatable: F)
+                                -> i32
+    where F: FnMut(LuaTable),
+          T: 'static + Any
+{
+    push_userdata(weak, lua, metatable)
+}
+
+/// Upgrades the handle pushed by `push_weak_userdata` at `index`.
+///
+/// Raises the Lua error "object destroyed" when the object was dropped, or "bad weak
+/// userdata" when the value isn't such a handle, so it must only be called from a native
+/// function, before any value needing a destructor is created.
+pub fn read_weak_userdata<T>(lua: *mut td_clua::lua_State, index: i32) -> Arc<Mutex<T>>
+    where T: 'static + Any
+{
+    let err_msg = match read_userdata::<Weak<Mutex<T>>>(lua, index) {
+        Some(weak) => match weak.upgrade() {
+            Some(obj) => return obj,
+            None => "object destroyed",
+        },
+        None => "bad weak userdata",
+    };
+    err_msg.push_to_lua(lua);
+    unsafe {
+        td_clua::lua_error(lua);
+    }
+    unreachable!()
+}
+
+/// Pushes an object shared between Rust and Lua as an instance of its struct, whose
+/// `LuaStruct::register_gc_shared` must have been called.
+///
+/// Lua holds a clone of the `Arc`, dropped when the userdata is collected, so the object
+/// lives as long as either side keeps it.
+pub fn push_shared_userdata<T>(data: Arc<Mutex<T>>, lua: *mut td_clua::lua_State) -> i32
+    where T: NewStruct + Any
+{
+    push_shared_instance(lua, data);
+    1
+}
+
 pub trait NewStruct {
     fn new() -> Self;
     fn name() -> &'static str;
@@ -272,25 +765,30 @@ impl<T> LuaStruct<T>
         self
     }
 
-    pub fn def<P>(&mut self, name: &str, param: P) -> &mut LuaStruct<T>
-        where P: LuaPush
+    // calls `f` with the table holding the methods, which is `__index` unless instance
+    // fields are enabled
+    fn with_methods<F>(&mut self, f: F)
+        where F: FnOnce(&mut LuaTable)
     {
         let tname = T::name();
         let mut lua = Lua::from_existing_state(self.lua, false);
-        match lua.query::<LuaTable, _>(tname.clone()) {
-            Some(mut table) => {
-                match table.query::<LuaTable, _>("__index") {
-                    Some(mut index) => {
-                        index.set(name, param);
-                    }
-                    None => {
-                        let mut index = table.empty_table("__index");
-                        index.set(name, param);
-                    }
-                };
+        if let Some(mut table) = lua.query::<LuaTable, _>(tname) {
+            if let Some(mut methods) = table.query::<LuaTable, _>("__methods") {
+                f(&mut methods);
+                return;
             }
-            None => (),
-        };
+            let mut index = match table.query::<LuaTable, _>("__index") {
+                Some(index) => index,
+                None => table.empty_table("__index"),
+            };
+            f(&mut index);
+        }
+    }
+
+    pub fn def<P>(&mut self, name: &str, param: P) -> &mut LuaStruct<T>
+        where P: LuaPush
+    {
+        self.with_methods(|methods| methods.set(name, param));
         self
     }
 
@@ -299,22 +797,294 @@ impl<T> LuaStruct<T>
                     name: &str,
                     func: extern "C" fn(*mut td_clua::lua_State) -> libc::c_int)
                     -> &mut LuaStruct<T> {
+        self.with_methods(|methods| methods.register(name, func));
+        self
+    }
+
+    /// Registers every `(name, func)` of `entries`, like calling `register` for each of them.
+    pub fn methods(&mut self,
+                   entries: &[(&str, extern "C" fn(*mut td_clua::lua_State) -> libc::c_int)])
+                   -> &mut LuaStruct<T> {
+        self.with_methods(|methods| {
+            for &(name, func) in entries {
+                methods.register(name, func);
+            }
+        });
+        self
+    }
+    /// Registers `method` under `name`, generating the glue that reads the object and the
+    /// arguments from the stack and pushes the result.
+    ///
+    /// `A` is a tuple for methods taking several arguments, or `()` for none, and is read from
+    /// the values following the object.
+    pub fn register_method<A, R>(&mut self, name: &str, method: fn(&mut T, A) -> R) -> &mut LuaStruct<T>
+        where A: LuaRead + 'static,
+              R: LuaPush + 'static
+    {
+        let lua = self.lua;
+        self.with_methods(|_| unsafe {
+            name.push_to_lua(lua);
+            push_userdata(Internal(method), lua, |_| {});
+            name.push_to_lua(lua);
+            td_clua::lua_pushcclosure(lua, method_wrapper::<T, A, R>, 2);
+            td_clua::lua_settable(lua, -3);
+        });
+        self
+    }
+
+    /// Same as `register_method` for methods taking `&self`, the object is only read, which
+    /// keeps getters from mutating it by mistake.
+    pub fn register_method_ref<A, R>(&mut self, name: &str, method: fn(&T, A) -> R) -> &mut LuaStruct<T>
+        where A: LuaRead + 'static,
+              R: LuaPush + 'static
+    {
+        let lua = self.lua;
+        self.with_methods(|_| unsafe {
+            name.push_to_lua(lua);
+            push_userdata(Internal(method), lua, |_| {});
+            name.push_to_lua(lua);
+            td_clua::lua_pushcclosure(lua, method_ref_wrapper::<T, A, R>, 2);
+            td_clua::lua_settable(lua, -3);
+        });
+        self
+    }
+
+    /// Adds an `obj:clone()` method returning a new instance holding a clone of the object,
+    /// with the same metatable and so the same methods.
+    pub fn register_clone(&mut self) -> &mut LuaStruct<T>
+        where T: Clone
+    {
+        let lua = self.lua;
+        let light = self.light;
+        self.with_methods(|_| unsafe {
+            "clone".push_to_lua(lua);
+            light.push_to_lua(lua);
+            td_clua::lua_pushcclosure(lua, clone_wrapper::<T>, 1);
+            td_clua::lua_settable(lua, -3);
+        });
+        self
+    }
+
+    /// Makes the instances `Arc<Mutex<T>>` shared with Rust instead of plain `T`, to be used
+    /// in place of `create`: `T()` in Lua makes a new shared object, `push_shared_userdata`
+    /// pushes an existing one, and the `__gc` drops the `Arc` rather than the object.
+    ///
+    /// The methods must then read the object with `read_userdata::<Arc<Mutex<T>>>`, reading
+    /// it as `T` fails, so `register_method` and the like can't be used. Light structs aren't
+    /// supported.
+    pub fn register_gc_shared(&mut self) -> &mut LuaStruct<T> {
+        assert!(!self.light, "light structs can't be shared");
+        self.ensure_matetable();
+        let tname = T::name();
+        let mut lua = Lua::from_existing_state(self.lua, false);
+        if let Some(mut table) = lua.query::<LuaTable, _>(tname) {
+            table.set("__typeid", format!("{:?}", TypeId::of::<Arc<Mutex<T>>>()));
+            table.register("__gc", destructor_wrapper::<Arc<Mutex<T>>>);
+            let mut constructor = table.get_or_create_metatable();
+            constructor.register("__call", constructor_shared_wrapper::<T>);
+        }
+        self
+    }
+
+    /// Hides the metatable from scripts, `getmetatable(obj)` returns the string "protected"
+    /// so its methods can't be replaced. The C API, used by this crate, isn't affected.
+    pub fn protect_metatable(&mut self) -> &mut LuaStruct<T> {
+        let tname = T::name();
+        let mut lua = Lua::from_existing_state(self.lua, false);
+        if let Some(mut table) = lua.query::<LuaTable, _>(tname) {
+            table.set("__metatable", "protected");
+        }
+        self
+    }
+
+    /// Lets scripts store their own fields on each instance, `obj.custom = 1` keeps the value
+    /// in a table attached to that object as its uservalue.
+    ///
+    /// Methods still take precedence when reading. The methods table moves to the
+    /// `__methods` field of the metatable, `__index` and `__newindex` become functions.
+    /// Light instances have no uservalue, this only works with `LuaStruct::new`.
+    pub fn enable_instance_fields(&mut self) -> &mut LuaStruct<T> {
+        let tname = T::name();
+        let mut lua = Lua::from_existing_state(self.lua, false);
+        if let Some(mut table) = lua.query::<LuaTable, _>(tname) {
+            if table.query::<LuaTable, _>("__methods").is_some() {
+                return self;
+            }
+            let methods = match table.query::<LuaTable, _>("__index") {
+                Some(index) => index,
+                None => table.empty_table("__index"),
+            };
+            unsafe {
+                "__methods".push_to_lua(self.lua);
+                td_clua::lua_pushvalue(self.lua, -2);
+                td_clua::lua_settable(self.lua, -4);
+
+                "__index".push_to_lua(self.lua);
+                td_clua::lua_pushvalue(self.lua, -2);
+                td_clua::lua_pushcclosure(self.lua, instance_index_wrapper, 1);
+                td_clua::lua_settable(self.lua, -4);
+            }
+            drop(methods);
+            table.register("__newindex", instance_newindex_wrapper);
+        }
+        self
+    }
+
+    /// Exposes a field of the instances to Lua: reading `obj.name` calls `get` and
+    /// `obj.name = value` calls `set` with the value read as `W`.
+    ///
+    /// Fields take precedence over methods. The first field registered turns `__index` and
+    /// `__newindex` into functions dispatching on the field name, falling back on what they
+    /// were before; the methods table moves to the `__methods` field of the metatable. Call
+    /// `register_newindex`, `forbid_newindex` or `enable_instance_fields` before registering
+    /// fields.
+    pub fn register_field<R, W>(&mut self, name: &str, get: fn(&T) -> R, set: fn(&mut T, W)) -> &mut LuaStruct<T>
+        where R: LuaPush + 'static,
+              W: LuaRead + 'static
+    {
+        self.add_field(name, get, field_getter_wrapper::<T, R>, set)
+    }
+
+    /// Same as `register_field` for a string field whose getter may borrow from the object,
+    /// the borrowed case is pushed without allocating a `String`.
+    pub fn register_str_field<W>(&mut self, name: &str, get: fn(&T) -> Cow<str>, set: fn(&mut T, W)) -> &mut LuaStruct<T>
+        where W: LuaRead + 'static
+    {
+        self.add_field(name, get, field_str_getter_wrapper::<T>, set)
+    }
+
+    // installs the dispatching metamethods on the first call, then stores `get` and `set` as
+    // the upvalue of `getter` and `field_setter_wrapper`
+    fn add_field<G, W>(&mut self,
+                       name: &str,
+                       get: G,
+                       getter: extern "C" fn(*mut td_clua::lua_State) -> libc::c_int,
+                       set: fn(&mut T, W))
+                       -> &mut LuaStruct<T>
+        where G: 'static,
+              W: LuaRead + 'static
+    {
         let tname = T::name();
         let mut lua = Lua::from_existing_state(self.lua, false);
-        match lua.query::<LuaTable, _>(tname.clone()) {
-            Some(mut table) => {
-                match table.query::<LuaTable, _>("__index") {
-                    Some(mut index) => {
-                        index.register(name, func);
+        let state = self.lua;
+        if let Some(mut table) = lua.query::<LuaTable, _>(tname) {
+            if table.query::<LuaTable, _>("__getters").is_none() {
+                if table.query::<LuaTable, _>("__methods").is_none() {
+                    let methods = match table.query::<LuaTable, _>("__index") {
+                        Some(index) => index,
+                        None => table.empty_table("__index"),
+                    };
+                    drop(methods);
+                    unsafe {
+                        "__methods".push_to_lua(state);
+                        "__index".push_to_lua(state);
+                        td_clua::lua_rawget(state, -3);
+                        td_clua::lua_rawset(state, -3);
                     }
-                    None => {
-                        let mut index = table.empty_table("__index");
-                        index.register(name, func);
+                }
+                unsafe {
+                    for &(fields, metamethod, wrapper) in &[("__getters", "__index", field_index_wrapper as extern "C" fn(*mut td_clua::lua_State) -> libc::c_int),
+                                                            ("__setters", "__newindex", field_newindex_wrapper)] {
+                        metamethod.push_to_lua(state);
+                        td_clua::lua_newtable(state);
+                        fields.push_to_lua(state);
+                        td_clua::lua_pushvalue(state, -2);
+                        td_clua::lua_rawset(state, -5);
+                        metamethod.push_to_lua(state);
+                        td_clua::lua_rawget(state, -4);
+                        // the fields table and the previous metamethod as upvalues
+                        td_clua::lua_pushcclosure(state, wrapper, 2);
+                        td_clua::lua_rawset(state, -3);
                     }
-                };
+                }
+            }
+
+            unsafe {
+                "__getters".push_to_lua(state);
+                td_clua::lua_rawget(state, -2);
+                name.push_to_lua(state);
+                push_userdata(Internal(get), state, |_| {});
+                name.push_to_lua(state);
+                td_clua::lua_pushcclosure(state, getter, 2);
+                td_clua::lua_rawset(state, -3);
+                td_clua::lua_pop(state, 1);
+
+                "__setters".push_to_lua(state);
+                td_clua::lua_rawget(state, -2);
+                name.push_to_lua(state);
+                push_userdata(Internal(set), state, |_| {});
+                name.push_to_lua(state);
+                td_clua::lua_pushcclosure(state, field_setter_wrapper::<T, W>, 2);
+                td_clua::lua_rawset(state, -3);
+                td_clua::lua_pop(state, 1);
+            }
+        }
+        self
+    }
+
+    /// Installs `func` as the `__newindex` metamethod of the instances, called with the
+    /// object, the key and the value for every `obj.key = value` from Lua.
+    pub fn register_newindex(&mut self,
+                             func: extern "C" fn(*mut td_clua::lua_State) -> libc::c_int)
+                             -> &mut LuaStruct<T> {
+        let tname = T::name();
+        let mut lua = Lua::from_existing_state(self.lua, false);
+        if let Some(mut table) = lua.query::<LuaTable, _>(tname) {
+            table.register("__newindex", func);
+        }
+        self
+    }
+
+    /// Makes `a == b` compare the content of the instances with `PartialEq` rather than their
+    /// identity. Comparing with a value that isn't a `T` gives false.
+    pub fn register_eq_by(&mut self) -> &mut LuaStruct<T>
+        where T: PartialEq
+    {
+        let tname = T::name();
+        let mut lua = Lua::from_existing_state(self.lua, false);
+        if let Some(mut table) = lua.query::<LuaTable, _>(tname) {
+            table.register("__eq", eq_wrapper::<T>);
+        }
+        self
+    }
+
+    /// Installs a `__tostring` formatting the object with `{:?}`, so `tostring(obj)` and
+    /// `print(obj)` give something readable for types without `Display`.
+    pub fn register_tostring_debug(&mut self) -> &mut LuaStruct<T>
+        where T: Debug
+    {
+        let tname = T::name();
+        let mut lua = Lua::from_existing_state(self.lua, false);
+        if let Some(mut table) = lua.query::<LuaTable, _>(tname) {
+            table.register("__tostring", tostring_debug_wrapper::<T>);
+        }
+        self
+    }
+
+    /// Makes the instances immutable from Lua, any field assignment raises an error.
+    pub fn forbid_newindex(&mut self) -> &mut LuaStruct<T> {
+        self.register_newindex(forbid_newindex_wrapper)
+    }
+
+    /// Installs a single handler for every method not registered with `def` or `register`.
+    ///
+    /// The handler receives the object, the method name and the state, with the call
+    /// arguments starting at stack index 2. It returns the number of values pushed, or
+    /// `None` when it doesn't know the method, which raises a Lua error. A panic is handled
+    /// according to the `PanicBehavior` of the state.
+    pub fn register_dispatch<F>(&mut self, dispatcher: F) -> &mut LuaStruct<T>
+        where F: FnMut(&mut T, &str, *mut lua_State) -> Option<i32> + 'static
+    {
+        let lua = self.lua;
+        self.with_methods(|methods| {
+            let _meta = methods.get_or_create_metatable();
+            unsafe {
+                "__index".push_to_lua(lua);
+                push_userdata(Internal(dispatcher), lua, |_| {});
+                td_clua::lua_pushcclosure(lua, dispatch_index_wrapper::<T, F>, 1);
+                td_clua::lua_settable(lua, -3);
             }
-            None => (),
-        };
+        });
         self
     }
 }
@@ -0,0 +1,70 @@
+use libc;
+use td_clua;
+
+use super::Lua;
+use LuaPush;
+use LuaRead;
+use LuaTable;
+
+// UTF-8 aware string helpers, counting characters instead of bytes
+
+// turns a Lua string position (1-based, negative counts from the end) into a 1-based
+// character position clamped to `0..=len`
+fn char_position(pos: i64, len: i64) -> i64 {
+    if pos >= 0 {
+        pos.min(len)
+    } else {
+        (len + pos + 1).max(0)
+    }
+}
+
+// raises a Lua error with a static message, nothing owned may be live in the caller
+fn arg_error(lua: *mut td_clua::lua_State, msg: &'static str) -> libc::c_int {
+    msg.push_to_lua(lua);
+    unsafe {
+        td_clua::lua_error(lua);
+    }
+    unreachable!()
+}
+
+// u8s.len(s)
+extern "C" fn utf8_len(lua: *mut td_clua::lua_State) -> libc::c_int {
+    let len = match String::lua_read_at_position(lua, 1) {
+        Some(s) => s.chars().count() as i64,
+        None => return arg_error(lua, "bad argument #1 to 'len' (string expected)"),
+    };
+    len.push_to_lua(lua)
+}
+
+fn sub(lua: *mut td_clua::lua_State) -> Result<String, &'static str> {
+    let s = String::lua_read_at_position(lua, 1).ok_or("bad argument #1 to 'sub' (string expected)")?;
+    let i = i64::lua_read_at_position(lua, 2).ok_or("bad argument #2 to 'sub' (number expected)")?;
+    let j = if unsafe { td_clua::lua_isnoneornil(lua, 3) } {
+        -1
+    } else {
+        i64::lua_read_at_position(lua, 3).ok_or("bad argument #3 to 'sub' (number expected)")?
+    };
+
+    let len = s.chars().count() as i64;
+    let start = char_position(i, len).max(1);
+    let end = char_position(j, len);
+    if start > end {
+        return Ok(String::new());
+    }
+    Ok(s.chars().skip(start as usize - 1).take((end - start + 1) as usize).collect())
+}
+
+// u8s.sub(s, i [, j]), same rules as `string.sub` with character positions
+extern "C" fn utf8_sub(lua: *mut td_clua::lua_State) -> libc::c_int {
+    match sub(lua) {
+        Ok(s) => s.push_to_lua(lua),
+        Err(msg) => arg_error(lua, msg),
+    }
+}
+
+/// Installs the `u8s` table holding `len` and `sub`.
+pub fn load_utf8_helpers(lua: &mut Lua) {
+    let mut table: LuaTable = lua.empty_table("u8s");
+    table.register("len", utf8_len);
+    table.register("sub", utf8_sub);
+}
@@ -1,5 +1,12 @@
+use std::borrow::Cow;
 use std::ffi::{CStr, CString};
+use std::fmt::Display;
 use std::mem;
+use std::ptr;
+use std::rc::Rc;
+use std::slice;
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
 
 use td_clua;
 use td_clua::lua_State;
@@ -17,6 +24,12 @@ macro_rules! integer_impl(
             }
         }
 
+        impl<'a> LuaPush for &'a $t {
+            fn push_to_lua(self, lua: *mut lua_State) -> i32 {
+                (*self).push_to_lua(lua)
+            }
+        }
+
         impl LuaRead for $t {
             fn lua_read_with_pop(lua: *mut lua_State, index: i32, _pop: i32) -> Option<$t> {
                 let mut success = unsafe { mem::uninitialized() };
@@ -26,10 +39,48 @@ macro_rules! integer_impl(
                     _ => Some(val as $t)
                 }
             }
+
+            fn lua_type_name() -> &'static str {
+                "number"
+            }
+        }
+
+        impl LuaPush for Lenient<$t> {
+            fn push_to_lua(self, lua: *mut lua_State) -> i32 {
+                self.0.push_to_lua(lua)
+            }
+        }
+
+        impl LuaRead for Lenient<$t> {
+            fn lua_read_with_pop(lua: *mut lua_State, index: i32, _pop: i32) -> Option<Lenient<$t>> {
+                let mut success = 0;
+                let val = match unsafe { td_clua::lua_type(lua, index) } {
+                    td_clua::LUA_TNUMBER | td_clua::LUA_TSTRING => unsafe {
+                        td_clua::lua_tointegerx(lua, index, &mut success)
+                    },
+                    _ => 0,
+                };
+                match success {
+                    0 => None,
+                    _ => Some(Lenient(val as $t)),
+                }
+            }
+
+            fn lua_type_name() -> &'static str {
+                "number"
+            }
         }
     );
 );
 
+/// An integer that also reads from a numeric string such as `"42"`, `" 0x2A "` or `"42.0"`.
+///
+/// The conversion is the one of the Lua arithmetic operators: the string must hold a number
+/// with an exact integer value, so `"abc"` or `"4.5"` fail to read. Use it where the source of
+/// the value is free to quote numbers, like hand-written config files.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Lenient<T>(pub T);
+
 integer_impl!(i8);
 integer_impl!(i16);
 integer_impl!(i32);
@@ -39,6 +90,7 @@ integer_impl!(u16);
 integer_impl!(u32);
 integer_impl!(u64);
 integer_impl!(usize);
+integer_impl!(isize);
 
 macro_rules! numeric_impl(
     ($t:ident) => (
@@ -49,6 +101,12 @@ macro_rules! numeric_impl(
             }
         }
 
+        impl<'a> LuaPush for &'a $t {
+            fn push_to_lua(self, lua: *mut lua_State) -> i32 {
+                (*self).push_to_lua(lua)
+            }
+        }
+
         impl LuaRead for $t {
             fn lua_read_with_pop(lua: *mut lua_State, index: i32, _pop: i32) -> Option<$t> {
                 let mut success = unsafe { mem::uninitialized() };
@@ -58,10 +116,39 @@ macro_rules! numeric_impl(
                     _ => Some(val as $t)
                 }
             }
+
+            fn lua_type_name() -> &'static str {
+                "number"
+            }
+        }
+
+        impl LuaPush for Finite<$t> {
+            fn push_to_lua(self, lua: *mut lua_State) -> i32 {
+                self.0.push_to_lua(lua)
+            }
+        }
+
+        impl LuaRead for Finite<$t> {
+            fn lua_read_with_pop(lua: *mut lua_State, index: i32, pop: i32) -> Option<Finite<$t>> {
+                match $t::lua_read_with_pop(lua, index, pop) {
+                    Some(val) if val.is_finite() => Some(Finite(val)),
+                    _ => None,
+                }
+            }
+
+            fn lua_type_name() -> &'static str {
+                "number"
+            }
         }
     );
 );
 
+/// A float that only reads successfully when it is neither NaN nor infinite.
+///
+/// Plain `f32`/`f64` read every Lua number as is, including NaN and infinities.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct Finite<T>(pub T);
+
 numeric_impl!(f32);
 numeric_impl!(f64);
 
@@ -90,16 +177,127 @@ impl LuaRead for String {
         let c_str = String::from_utf8_lossy(c_str.to_bytes());
         Some(c_str.to_string())
     }
+
+    fn lua_type_name() -> &'static str {
+        "string"
+    }
+}
+
+// calls `f` with the string at `index` decoded like `String` reads it, without allocating
+// when it is valid UTF-8
+fn read_lossy_str<R, F>(lua: *mut lua_State, index: i32, f: F) -> Option<R>
+    where F: FnOnce(&str) -> R
+{
+    let c_str_raw = unsafe { td_clua::lua_tolstring(lua, index, ptr::null_mut()) };
+    if c_str_raw.is_null() {
+        return None;
+    }
+    let c_str = unsafe { CStr::from_ptr(c_str_raw) };
+    Some(f(&String::from_utf8_lossy(c_str.to_bytes())))
+}
+
+/// Reads a string into shared storage like `String` reads it, numbers included, without the
+/// intermediate `String`.
+impl LuaRead for Arc<str> {
+    fn lua_read_with_pop(lua: *mut lua_State, index: i32, _pop: i32) -> Option<Arc<str>> {
+        read_lossy_str(lua, index, |s| Arc::from(s))
+    }
+
+    fn lua_type_name() -> &'static str {
+        "string"
+    }
+}
+
+/// Same as `Arc<str>`.
+impl LuaRead for Rc<str> {
+    fn lua_read_with_pop(lua: *mut lua_State, index: i32, _pop: i32) -> Option<Rc<str>> {
+        read_lossy_str(lua, index, |s| Rc::from(s))
+    }
+
+    fn lua_type_name() -> &'static str {
+        "string"
+    }
+}
+
+/// Pushes a borrowed string slice, letting Lua copy the bytes directly.
+///
+/// Unlike going through `String`, no intermediate `CString` is allocated on the Rust side,
+/// which makes it the cheap way for userdata methods to return a string field.
+pub fn push_str_from(lua: *mut lua_State, value: &str) -> i32 {
+    unsafe { td_clua::lua_pushlstring(lua, value.as_ptr() as *const libc::c_char, value.len()) };
+    1
 }
 
 impl<'s> LuaPush for &'s str {
     fn push_to_lua(self, lua: *mut lua_State) -> i32 {
-        let value = CString::new(&self[..]).unwrap();
-        unsafe { td_clua::lua_pushstring(lua, value.as_ptr()) };
+        push_str_from(lua, self)
+    }
+}
+
+/// Pushes the string as is, borrowed or owned, without going through a `String`.
+impl<'a> LuaPush for Cow<'a, str> {
+    fn push_to_lua(self, lua: *mut lua_State) -> i32 {
+        push_str_from(lua, &self)
+    }
+}
+
+/// Pushes the bytes as a Lua string, embedded NULs included.
+impl<'a> LuaPush for Cow<'a, [u8]> {
+    fn push_to_lua(self, lua: *mut lua_State) -> i32 {
+        unsafe { td_clua::lua_pushlstring(lua, self.as_ptr() as *const libc::c_char, self.len()) };
         1
     }
 }
 
+/// Reads a copy of the bytes of a Lua string, always as `Cow::Owned` since the Lua buffer may
+/// be collected once the value is off the stack. Numbers aren't converted.
+impl<'a> LuaRead for Cow<'a, [u8]> {
+    fn lua_read_with_pop(lua: *mut lua_State, index: i32, _pop: i32) -> Option<Cow<'a, [u8]>> {
+        if unsafe { td_clua::lua_type(lua, index) } != td_clua::LUA_TSTRING {
+            return None;
+        }
+        let mut size = 0;
+        let data = unsafe { td_clua::lua_tolstring(lua, index, &mut size) };
+        let bytes = unsafe { slice::from_raw_parts(data as *const u8, size) };
+        Some(Cow::Owned(bytes.to_vec()))
+    }
+
+    fn lua_type_name() -> &'static str {
+        "string"
+    }
+}
+
+/// An ASCII character, pushed as a one-byte string.
+///
+/// Reading it only accepts a string of exactly one byte below 128, numbers aren't converted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Ascii(pub u8);
+
+impl LuaPush for Ascii {
+    fn push_to_lua(self, lua: *mut lua_State) -> i32 {
+        unsafe { td_clua::lua_pushlstring(lua, &self.0 as *const u8 as *const libc::c_char, 1) };
+        1
+    }
+}
+
+impl LuaRead for Ascii {
+    fn lua_read_with_pop(lua: *mut lua_State, index: i32, _pop: i32) -> Option<Ascii> {
+        if unsafe { td_clua::lua_type(lua, index) } != td_clua::LUA_TSTRING {
+            return None;
+        }
+        let mut size = 0;
+        let data = unsafe { td_clua::lua_tolstring(lua, index, &mut size) };
+        match unsafe { slice::from_raw_parts(data as *const u8, size) } {
+            &[byte] if byte < 128 => Some(Ascii(byte)),
+            _ => None,
+        }
+    }
+
+    fn lua_type_name() -> &'static str {
+        "string"
+    }
+}
+
 impl LuaPush for bool {
     fn push_to_lua(self, lua: *mut lua_State) -> i32 {
         unsafe { td_clua::lua_pushboolean(lua, self.clone() as libc::c_int) };
@@ -107,6 +305,12 @@ impl LuaPush for bool {
     }
 }
 
+impl LuaPush for &bool {
+    fn push_to_lua(self, lua: *mut lua_State) -> i32 {
+        (*self).push_to_lua(lua)
+    }
+}
+
 impl LuaRead for bool {
     fn lua_read_with_pop(lua: *mut lua_State, index: i32, _pop: i32) -> Option<bool> {
         if unsafe { td_clua::lua_isboolean(lua, index) } != true {
@@ -115,6 +319,150 @@ impl LuaRead for bool {
 
         Some(unsafe { td_clua::lua_toboolean(lua, index) != 0 })
     }
+
+    fn lua_type_name() -> &'static str {
+        "boolean"
+    }
+}
+
+/// A boolean read with the truthiness of Lua conditions: nil and false are false, any other
+/// value is true. Reading it never fails, unlike `bool` which only accepts booleans.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Truthy(pub bool);
+
+impl LuaPush for Truthy {
+    fn push_to_lua(self, lua: *mut lua_State) -> i32 {
+        self.0.push_to_lua(lua)
+    }
+}
+
+impl LuaRead for Truthy {
+    fn lua_read_with_pop(lua: *mut lua_State, index: i32, _pop: i32) -> Option<Truthy> {
+        Some(Truthy(unsafe { td_clua::lua_toboolean(lua, index) != 0 }))
+    }
+}
+
+/// An `Instant` given to Lua as the number of seconds elapsed since `LuaInstant::base()`, a
+/// float.
+///
+/// The base is taken once for the whole process, the first time it is needed, so the numbers
+/// seen by scripts are comparable across states and calls, and reading one back gives the
+/// `Instant` it was pushed from, within the precision of an `f64`. Instants before the base
+/// are negative numbers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LuaInstant(pub Instant);
+
+static INSTANT_BASE: OnceLock<Instant> = OnceLock::new();
+
+impl LuaInstant {
+    /// The instant pushed as `0.0`.
+    pub fn base() -> Instant {
+        *INSTANT_BASE.get_or_init(Instant::now)
+    }
+}
+
+impl LuaPush for LuaInstant {
+    fn push_to_lua(self, lua: *mut lua_State) -> i32 {
+        let base = LuaInstant::base();
+        let secs = if self.0 >= base {
+            (self.0 - base).as_secs_f64()
+        } else {
+            -(base - self.0).as_secs_f64()
+        };
+        secs.push_to_lua(lua)
+    }
+}
+
+/// Fails for numbers that aren't finite or that give an instant the platform can't represent.
+impl LuaRead for LuaInstant {
+    fn lua_read_with_pop(lua: *mut lua_State, index: i32, pop: i32) -> Option<LuaInstant> {
+        let Finite(secs) = Finite::<f64>::lua_read_with_pop(lua, index, pop)?;
+        let offset = Duration::try_from_secs_f64(secs.abs()).ok()?;
+        let base = LuaInstant::base();
+        let instant = if secs >= 0.0 { base.checked_add(offset) } else { base.checked_sub(offset) };
+        instant.map(LuaInstant)
+    }
+
+    fn lua_type_name() -> &'static str {
+        "number"
+    }
+}
+
+/// Pushes the UUID as its lowercase hyphenated string, such as
+/// `"67e55044-10b1-426f-9247-bb680e5fe0c8"`.
+#[cfg(feature = "uuid")]
+impl LuaPush for uuid::Uuid {
+    fn push_to_lua(self, lua: *mut lua_State) -> i32 {
+        push_str_from(lua, self.hyphenated().encode_lower(&mut uuid::Uuid::encode_buffer()))
+    }
+}
+
+/// Parses a string in any of the forms accepted by `Uuid::parse_str`, failing on a malformed
+/// one. Numbers aren't converted.
+#[cfg(feature = "uuid")]
+impl LuaRead for uuid::Uuid {
+    fn lua_read_with_pop(lua: *mut lua_State, index: i32, _pop: i32) -> Option<uuid::Uuid> {
+        if unsafe { td_clua::lua_type(lua, index) } != td_clua::LUA_TSTRING {
+            return None;
+        }
+        read_lossy_str(lua, index, |s| uuid::Uuid::parse_str(s).ok())?
+    }
+
+    fn lua_type_name() -> &'static str {
+        "string"
+    }
+}
+
+impl<T> LuaPush for Box<T> where T: LuaPush {
+    fn push_to_lua(self, lua: *mut lua_State) -> i32 {
+        (*self).push_to_lua(lua)
+    }
+}
+
+impl<T> LuaRead for Box<T> where T: LuaRead {
+    fn lua_read_with_pop(lua: *mut lua_State, index: i32, pop: i32) -> Option<Box<T>> {
+        T::lua_read_with_pop(lua, index, pop).map(Box::new)
+    }
+
+    fn lua_type_name() -> &'static str {
+        T::lua_type_name()
+    }
+}
+
+/// Pushes the referenced value, or nil for `None`, so the result of a lookup can be pushed
+/// without copying the value out first. Numbers, booleans and `str` can be referenced.
+impl<'a, T: ?Sized> LuaPush for Option<&'a T> where &'a T: LuaPush {
+    fn push_to_lua(self, lua: *mut lua_State) -> i32 {
+        match self {
+            Some(value) => value.push_to_lua(lua),
+            None => ().push_to_lua(lua),
+        }
+    }
+}
+
+/// Meant as the return type of functions given to Lua that either succeed with nothing to
+/// return or fail: `Ok(())` pushes no value, `Err(e)` raises a Lua error with the message of
+/// `e`, prefixed with the position of the calling Lua code like `luaL_error` does.
+///
+/// The error doesn't return, so pushing an `Err` outside of a function called by Lua must be
+/// done under `Lua::protected`.
+impl<E> LuaPush for Result<(), E> where E: Display {
+    fn push_to_lua(self, lua: *mut lua_State) -> i32 {
+        let err = match self {
+            Ok(()) => return 0,
+            Err(err) => err,
+        };
+        let msg = err.to_string();
+        drop(err);
+        unsafe { td_clua::luaL_where(lua, 1) };
+        push_str_from(lua, &msg);
+        drop(msg);
+        unsafe {
+            td_clua::lua_concat(lua, 2);
+            td_clua::lua_error(lua);
+        }
+        unreachable!()
+    }
 }
 
 impl LuaPush for () {
@@ -1,6 +1,6 @@
 extern crate td_rlua;
 
-use td_rlua::Lua;
+use td_rlua::{Ascii, Finite, Lenient, Lua, LuaInstant, LuaPush, LuaRead, LuaTryPush, PushError, Truthy};
 
 #[test]
 fn read_i32s() {
@@ -64,6 +64,38 @@ fn readwrite_floats() {
     assert!(w - 3.4123456789 < 0.000001);
 }
 
+#[test]
+fn readwrite_non_finite_floats() {
+    let mut lua = Lua::new();
+
+    lua.set("nan", std::f64::NAN);
+    lua.set("inf", std::f64::INFINITY);
+    lua.set("ninf", std::f32::NEG_INFINITY);
+
+    let nan: f64 = lua.query("nan").unwrap();
+    assert!(nan.is_nan());
+    let inf: f64 = lua.query("inf").unwrap();
+    assert_eq!(inf, std::f64::INFINITY);
+    let ninf: f32 = lua.query("ninf").unwrap();
+    assert_eq!(ninf, std::f32::NEG_INFINITY);
+
+    let is_nan: bool = lua.exec_string("return nan ~= nan").unwrap();
+    assert!(is_nan);
+}
+
+#[test]
+fn finite_rejects_nan() {
+    let mut lua = Lua::new();
+
+    lua.set("nan", std::f64::NAN);
+    lua.set("inf", std::f64::INFINITY);
+    lua.set("x", 1.5);
+
+    assert!(lua.query::<Finite<f64>, _>("nan").is_none());
+    assert!(lua.query::<Finite<f32>, _>("inf").is_none());
+    assert_eq!(lua.query::<Finite<f64>, _>("x"), Some(Finite(1.5)));
+}
+
 #[test]
 fn readwrite_bools() {
     let mut lua = Lua::new();
@@ -97,6 +129,23 @@ fn readwrite_strings() {
     assert_eq!(z, "UNVAILED STRING");
 }
 
+#[test]
+fn read_shared_strings() {
+    use std::rc::Rc;
+    use std::sync::Arc;
+
+    let mut lua = Lua::new();
+    let _: () = lua.exec_string("a = 'hello' b = 'caf\\195\\169 \\255' c = 12 d = {}").unwrap();
+
+    let a: Arc<str> = lua.query("a").unwrap();
+    assert_eq!(&*a, "hello");
+    let b: Rc<str> = lua.query("b").unwrap();
+    assert_eq!(&*b, "café \u{fffd}");
+    let c: Arc<str> = lua.query("c").unwrap();
+    assert_eq!(&*c, "12");
+    assert!(lua.query::<Rc<str>, _>("d").is_none());
+}
+
 #[test]
 fn i32_to_string() {
     let mut lua = Lua::new();
@@ -120,3 +169,354 @@ fn string_to_i32() {
     let y: Option<i32> = lua.query("b");
     assert!(y.is_none());
 }
+
+#[test]
+fn copy_and_replace_values() {
+    let mut lua = Lua::new();
+
+    "hello".push_to_lua(lua.state());
+    lua.copy_value(-1);
+    let x: String = LuaRead::lua_read_at_position(lua.state(), -1).unwrap();
+    let y: String = LuaRead::lua_read_at_position(lua.state(), -2).unwrap();
+    assert_eq!(x, y);
+
+    5.push_to_lua(lua.state());
+    lua.replace(-3);
+    let z: i32 = LuaRead::lua_read_at_position(lua.state(), -2).unwrap();
+    assert_eq!(z, 5);
+    let w: String = LuaRead::lua_read_at_position(lua.state(), -1).unwrap();
+    assert_eq!(w, "hello");
+}
+
+#[test]
+fn concat_values() {
+    let mut lua = Lua::new();
+    let base = lua.top();
+
+    "a".push_to_lua(lua.state());
+    "b".push_to_lua(lua.state());
+    "c".push_to_lua(lua.state());
+    lua.concat(3);
+    assert_eq!(lua.top(), base + 1);
+    let x: String = LuaRead::lua_read(lua.state()).unwrap();
+    assert_eq!(x, "abc");
+
+    12.push_to_lua(lua.state());
+    lua.concat(2);
+    let y: String = LuaRead::lua_read(lua.state()).unwrap();
+    assert_eq!(y, "abc12");
+    lua.pop(1);
+    assert_eq!(lua.top(), base);
+}
+
+#[test]
+fn nil_predicates() {
+    let mut lua = Lua::new();
+    let base = lua.top();
+
+    ().push_to_lua(lua.state());
+    1.push_to_lua(lua.state());
+    assert!(lua.is_nil(-2));
+    assert!(lua.is_none_or_nil(-2));
+    assert!(!lua.is_nil(-1));
+    assert!(!lua.is_none_or_nil(-1));
+    assert!(!lua.is_nil(base + 3));
+    assert!(lua.is_none_or_nil(base + 3));
+    lua.pop(2);
+}
+
+#[test]
+fn check_type() {
+    use td_rlua::LuaType;
+
+    let mut lua = Lua::new();
+    let _: () = lua.exec_string("t = {}").unwrap();
+    let base = lua.top();
+
+    let table: td_rlua::LuaTable = lua.query("t").unwrap();
+    assert!(lua.check_type(-1, LuaType::Table));
+    assert!(!lua.check_type(-1, LuaType::Number));
+    drop(table);
+
+    "12".push_to_lua(lua.state());
+    assert!(lua.check_type(-1, LuaType::String));
+    assert!(!lua.check_type(-1, LuaType::Number));
+    assert!(lua.check_type(base + 2, LuaType::None));
+    lua.pop(1);
+}
+
+#[test]
+fn pop_and_top() {
+    let mut lua = Lua::new();
+    let base = lua.top();
+
+    1.push_to_lua(lua.state());
+    "two".push_to_lua(lua.state());
+    3.0.push_to_lua(lua.state());
+    assert_eq!(lua.top(), base + 3);
+
+    lua.pop(1);
+    assert_eq!(lua.top(), base + 2);
+    let x: String = LuaRead::lua_read_at_position(lua.state(), -1).unwrap();
+    assert_eq!(x, "two");
+
+    lua.pop(2);
+    assert_eq!(lua.top(), base);
+}
+
+#[test]
+fn try_push() {
+    struct Identifier(String);
+
+    impl LuaTryPush for Identifier {
+        fn try_push_to_lua(self, lua: *mut td_rlua::lua_State) -> Result<i32, PushError> {
+            if !self.0.chars().all(|c| c.is_ascii_alphanumeric()) {
+                return Err(PushError::new(format!("invalid identifier {:?}", self.0)));
+            }
+            Ok(self.0.push_to_lua(lua))
+        }
+    }
+
+    let mut lua = Lua::new();
+
+    assert_eq!(5.try_push_to_lua(lua.state()), Ok(1));
+    assert_eq!(Identifier("abc".to_string()).try_push_to_lua(lua.state()), Ok(1));
+    let x: String = LuaRead::lua_read(lua.state()).unwrap();
+    assert_eq!(x, "abc");
+
+    let err = Identifier("a b".to_string()).try_push_to_lua(lua.state()).unwrap_err();
+    assert_eq!(err.message, "invalid identifier \"a b\"");
+    let y: String = LuaRead::lua_read(lua.state()).unwrap();
+    assert_eq!(y, "abc");
+}
+
+#[test]
+fn lenient_integer_from_string() {
+    let mut lua = Lua::new();
+
+    lua.set("a", "42");
+    lua.set("b", "abc");
+    lua.set("c", 7);
+    lua.set("d", true);
+
+    assert_eq!(lua.query::<Lenient<i64>, _>("a"), Some(Lenient(42)));
+    assert_eq!(lua.query::<Lenient<i64>, _>("b"), None);
+    assert_eq!(lua.query::<Lenient<i64>, _>("c"), Some(Lenient(7)));
+    assert_eq!(lua.query::<Lenient<i64>, _>("d"), None);
+}
+
+#[test]
+fn number_subtypes() {
+    let mut lua = Lua::new();
+    lua.openlibs();
+
+    lua.set("a", 3i8);
+    lua.set("b", 3u16);
+    lua.set("c", 3i32);
+    lua.set("d", 3u64);
+    lua.set("e", 3usize);
+    lua.set("f", 3.0f32);
+    lua.set("g", 3.0f64);
+
+    for name in &["a", "b", "c", "d", "e"] {
+        let subtype: String = lua.exec_string(format!("return math.type({})", name)).unwrap();
+        assert_eq!(subtype, "integer");
+    }
+    for name in &["f", "g"] {
+        let subtype: String = lua.exec_string(format!("return math.type({})", name)).unwrap();
+        assert_eq!(subtype, "float");
+    }
+}
+
+#[test]
+fn readwrite_isize() {
+    let mut lua = Lua::new();
+
+    lua.set("a", -42isize);
+    let x: isize = lua.query("a").unwrap();
+    assert_eq!(x, -42);
+
+    lua.set("b", isize::MIN);
+    let y: isize = lua.query("b").unwrap();
+    assert_eq!(y, isize::MIN);
+}
+
+#[test]
+fn write_cow_str() {
+    use std::borrow::Cow;
+
+    let mut lua = Lua::new();
+
+    lua.set("a", Cow::Borrowed("borrowed"));
+    lua.set("b", Cow::Owned::<str>("owned".to_string()));
+
+    let a: String = lua.query("a").unwrap();
+    assert_eq!(a, "borrowed");
+    let b: String = lua.query("b").unwrap();
+    assert_eq!(b, "owned");
+}
+
+#[test]
+fn readwrite_ascii() {
+    let mut lua = Lua::new();
+
+    lua.set("a", Ascii(b'A'));
+    let a: String = lua.query("a").unwrap();
+    assert_eq!(a, "A");
+    assert_eq!(lua.query::<Ascii, _>("a"), Some(Ascii(b'A')));
+
+    let _: () = lua.exec_string("b = 'é' c = '\\200' d = 'AB' e = '' f = 7").unwrap();
+    for name in &["b", "c", "d", "e", "f"] {
+        assert_eq!(lua.query::<Ascii, _>(*name), None);
+    }
+}
+
+#[test]
+fn push_option_ref() {
+    let mut lua = Lua::new();
+
+    let values = vec![5, 7];
+    lua.set("a", values.first());
+    lua.set("b", values.get(9));
+    lua.set("c", Some(&2.5));
+    lua.set("d", Some("name"));
+    lua.set("e", None::<&i32>);
+
+    assert_eq!(lua.query::<i32, _>("a"), Some(5));
+    assert_eq!(lua.query::<f64, _>("c"), Some(2.5));
+    assert_eq!(lua.query::<String, _>("d"), Some("name".to_string()));
+    let nils: bool = lua.exec_string("return b == nil and e == nil").unwrap();
+    assert!(nils);
+}
+
+#[test]
+fn readwrite_box() {
+    let mut lua = Lua::new();
+
+    lua.set("a", Box::new(12));
+    let x: Box<i32> = lua.query("a").unwrap();
+    assert_eq!(*x, 12);
+
+    lua.set("b", "text");
+    assert!(lua.query::<Box<i32>, _>("b").is_none());
+}
+
+#[test]
+fn readwrite_instant() {
+    use std::time::Duration;
+
+    let mut lua = Lua::new();
+
+    let base = LuaInstant::base();
+    let later = base + Duration::from_millis(100);
+    lua.set("t", LuaInstant(later));
+
+    let secs: f64 = lua.query("t").unwrap();
+    assert!((secs - 0.1).abs() < 1e-6);
+
+    let LuaInstant(read) = lua.query("t").unwrap();
+    let diff = if read > later { read - later } else { later - read };
+    assert!(diff < Duration::from_micros(1));
+
+    let _: () = lua.exec_string("t = t - 0.5").unwrap();
+    let LuaInstant(earlier) = lua.query("t").unwrap();
+    assert!(earlier < base);
+}
+
+#[test]
+fn compare_in_place() {
+    let mut lua = Lua::new();
+
+    5.push_to_lua(lua.state());
+    2.5.push_to_lua(lua.state());
+    "5".push_to_lua(lua.state());
+    4.0.push_to_lua(lua.state());
+
+    assert!(lua.number_eq(-4, 5.0));
+    assert!(!lua.number_eq(-4, 6.0));
+    assert!(lua.integer_eq(-4, 5));
+    assert!(!lua.integer_eq(-4, 4));
+
+    assert!(lua.number_eq(-3, 2.5));
+    assert!(!lua.integer_eq(-3, 2));
+
+    assert!(!lua.number_eq(-2, 5.0));
+    assert!(!lua.integer_eq(-2, 5));
+    assert!(lua.str_eq(-2, "5"));
+    assert!(!lua.str_eq(-4, "5"));
+
+    assert!(lua.integer_eq(-1, 4));
+}
+
+#[test]
+fn read_truthy() {
+    let mut lua = Lua::new();
+
+    let _: Option<()> = lua.exec_string("a = 0; b = ''; c = nil; d = false; e = {}");
+
+    assert_eq!(lua.query::<Truthy, _>("a"), Some(Truthy(true)));
+    assert_eq!(lua.query::<Truthy, _>("b"), Some(Truthy(true)));
+    assert_eq!(lua.query::<Truthy, _>("c"), Some(Truthy(false)));
+    assert_eq!(lua.query::<Truthy, _>("d"), Some(Truthy(false)));
+    assert_eq!(lua.query::<Truthy, _>("e"), Some(Truthy(true)));
+
+    assert_eq!(lua.query::<bool, _>("a"), None);
+}
+
+#[test]
+fn readwrite_cow_bytes() {
+    use std::borrow::Cow;
+
+    let mut lua = Lua::new();
+
+    let data = b"\x00abc\x00\xff";
+    lua.set("a", Cow::Borrowed(&data[..]));
+    lua.set("b", Cow::Owned::<[u8]>(vec![1, 0, 2]));
+
+    let len: i32 = lua.exec_string("return #a").unwrap();
+    assert_eq!(len, 6);
+
+    let a: Cow<[u8]> = lua.query("a").unwrap();
+    assert_eq!(&a[..], &data[..]);
+    assert!(match a { Cow::Owned(_) => true, _ => false });
+    let b: Cow<[u8]> = lua.query("b").unwrap();
+    assert_eq!(&b[..], &[1, 0, 2][..]);
+
+    lua.set("c", 12);
+    assert!(lua.query::<Cow<[u8]>, _>("c").is_none());
+}
+
+#[test]
+fn readwrite_large_tuple() {
+    let mut lua = Lua::new();
+    let base = lua.top();
+
+    let count = (1, "two", 3.5, true, "five".to_string(), 6u8, -7i64, "eight").push_to_lua(lua.state());
+    assert_eq!(count, 8);
+    let values: (i32, String, f64, bool, String, u8, i64, String) = lua.read_results(8).unwrap();
+    assert_eq!(values, (1, "two".to_string(), 3.5, true, "five".to_string(), 6, -7, "eight".to_string()));
+    assert_eq!(lua.top(), base);
+
+    (1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12).push_to_lua(lua.state());
+    let values: (i32, i32, i32, i32, i32, i32, i32, i32, i32, i32, i32, i32) = lua.read_results(12).unwrap();
+    assert_eq!(values, (1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12));
+    assert_eq!(lua.top(), base);
+}
+
+#[test]
+#[cfg(feature = "uuid")]
+fn uuid_strings() {
+    let mut lua = Lua::new();
+
+    let id = uuid::Uuid::new_v4();
+    lua.set("id", id);
+    let text: String = lua.query("id").unwrap();
+    assert_eq!(text, id.hyphenated().to_string());
+    let back: uuid::Uuid = lua.query("id").unwrap();
+    assert_eq!(back, id);
+
+    lua.set("bad", "67e55044-10b1-426f-9247");
+    assert_eq!(lua.query::<uuid::Uuid, _>("bad"), None);
+    lua.set("number", 12);
+    assert_eq!(lua.query::<uuid::Uuid, _>("number"), None);
+}
@@ -0,0 +1,96 @@
+extern crate td_rlua;
+
+use std::error::Error;
+use std::io;
+
+use td_rlua::{Lua, LuaError};
+
+#[test]
+fn display_variants() {
+    let err = LuaError::Syntax("chunk:1: unexpected symbol".to_string());
+    assert_eq!(err.to_string(), "syntax error: chunk:1: unexpected symbol");
+    assert_eq!(err.message(), "chunk:1: unexpected symbol");
+
+    assert_eq!(LuaError::Runtime("boom".to_string()).to_string(), "runtime error: boom");
+    assert_eq!(LuaError::Memory("not enough memory".to_string()).to_string(),
+               "memory error: not enough memory");
+    assert_eq!(LuaError::Gc("bad finalizer".to_string()).to_string(),
+               "error in __gc metamethod: bad finalizer");
+    assert_eq!(LuaError::MessageHandler("loop".to_string()).to_string(),
+               "error in message handler: loop");
+
+    let err = LuaError::from(io::Error::new(io::ErrorKind::Other, "disconnected"));
+    assert_eq!(err.to_string(), "read error: disconnected");
+    assert!(err.source().is_some());
+}
+
+#[test]
+fn traceback_part() {
+    let mut lua = Lua::new();
+    lua.openlibs();
+
+    let err = LuaError::Runtime("boom".to_string());
+    assert!(err.traceback().is_none());
+
+    let msg: String = lua.exec_string("return debug.traceback('boom')").unwrap();
+    let err = LuaError::Runtime(msg);
+    assert!(err.traceback().unwrap().starts_with("stack traceback:"));
+    assert!(err.to_string().starts_with("runtime error: boom\nstack traceback:"));
+}
+
+#[test]
+fn works_with_question_mark() {
+    fn run(lua: &mut Lua) -> Result<(), Box<dyn Error>> {
+        lua.load_reader("x = = 1".as_bytes(), "broken")?;
+        Ok(())
+    }
+
+    let mut lua = Lua::new();
+    let err = run(&mut lua).unwrap_err();
+    assert!(err.to_string().starts_with("syntax error: "));
+}
+
+#[test]
+fn named_chunk() {
+    let mut lua = Lua::new();
+    lua.openlibs();
+
+    let code = "local function check(v)\n  error('bad value ' .. v)\nend\ncheck(3)";
+    let err = lua.load_string_named(code, "my_plugin").unwrap_err();
+    assert!(err.message().starts_with("my_plugin:2: bad value 3"));
+    let traceback = err.traceback().unwrap();
+    assert!(traceback.contains("my_plugin:4: in main chunk"), "{}", traceback);
+    assert_eq!(lua.top(), 0);
+
+    match lua.load_string_named("x = = 1", "broken") {
+        Err(LuaError::Syntax(msg)) => assert!(msg.starts_with("broken:1:")),
+        _ => panic!(),
+    }
+
+    lua.load_string_named("x = 5", "ok").unwrap();
+    let x: i32 = lua.query("x").unwrap();
+    assert_eq!(x, 5);
+}
+
+#[test]
+fn run_all() {
+    let mut lua = Lua::new();
+    lua.openlibs();
+
+    let results = lua.run_all(&["x = 1", "x = x + 1; error('stop')", "y = x * 10", "z = = 1"]);
+    assert_eq!(results.len(), 4);
+    assert!(results[0].is_ok());
+    match results[1] {
+        Err(LuaError::Runtime(ref msg)) => assert_eq!(msg, "chunk 2:1: stop"),
+        _ => panic!(),
+    }
+    assert!(results[2].is_ok());
+    match results[3] {
+        Err(LuaError::Syntax(ref msg)) => assert!(msg.starts_with("chunk 4:1:")),
+        _ => panic!(),
+    }
+
+    let y: i32 = lua.query("y").unwrap();
+    assert_eq!(y, 20);
+    assert_eq!(lua.top(), 0);
+}
@@ -174,4 +174,553 @@ fn test_exec_func_by_param() {
     let _: Option<()> = lua.exec_string(func);
     let ret: Option<i32> = lua.exec_string("return test();");
     assert_eq!(ret.unwrap(), 6);
-}
\ No newline at end of file
+}
+
+#[test]
+fn panic_converted_to_error() {
+    let mut lua = Lua::new();
+    lua.openlibs();
+    lua.set_panic_behavior(td_rlua::PanicBehavior::ConvertToError);
+
+    lua.set("boom", td_rlua::function0(|| -> i32 { panic!("boom!") }));
+    let err: String = lua.exec_string("local ok, err = pcall(boom); return err").unwrap();
+    assert_eq!(err, "boom!");
+
+    let val: i32 = lua.exec_string("return 1 + 1").unwrap();
+    assert_eq!(val, 2);
+}
+
+#[test]
+fn panic_custom_handler() {
+    use std::sync::{Arc, Mutex};
+
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let mut lua = Lua::new();
+    lua.openlibs();
+    {
+        let seen = seen.clone();
+        lua.set_panic_behavior(td_rlua::PanicBehavior::Custom(Box::new(move |msg| {
+            seen.lock().unwrap().push(msg.to_string());
+            format!("custom: {}", msg)
+        })));
+    }
+
+    lua.set("boom", td_rlua::function1(|val: i32| -> i32 { panic!("bad value {}", val) }));
+    let err: String = lua.exec_string("local ok, err = pcall(boom, 3); return err").unwrap();
+    assert_eq!(err, "custom: bad value 3");
+    assert_eq!(*seen.lock().unwrap(), vec!["bad value 3".to_string()]);
+}
+
+#[test]
+fn panic_location_in_error() {
+    let mut lua = Lua::new();
+    lua.openlibs();
+    lua.set_panic_behavior(td_rlua::PanicBehavior::ConvertToError);
+    lua.traceback_on_panic(true);
+
+    lua.set("boom", td_rlua::function0(|| -> i32 { panic!("boom!") }));
+    let line = line!() - 1;
+    let err: String = lua.exec_string("local ok, err = pcall(boom); return err").unwrap();
+    assert!(err.starts_with(&format!("boom! (panicked at tests/functions.rs:{}", line)));
+
+    lua.traceback_on_panic(false);
+    let err: String = lua.exec_string("local ok, err = pcall(boom); return err").unwrap();
+    assert_eq!(err, "boom!");
+}
+
+#[test]
+fn raise_formatted_error() {
+    let mut lua = Lua::new();
+    lua.openlibs();
+
+    extern "C" fn check_port(lua: *mut td_rlua::lua_State) -> libc::c_int {
+        let port: i32 = td_rlua::LuaRead::lua_read_at_position(lua, 1).unwrap();
+        if port > 65535 {
+            let mut lua = Lua::from_existing_state(lua, false);
+            lua.raise_error(format!("invalid port {}", port));
+        }
+        0
+    }
+    lua.register("check_port", check_port);
+
+    let err: String = lua.exec_string("local ok, err = pcall(check_port, 70000); return err").unwrap();
+    assert_eq!(err, "invalid port 70000");
+
+    let err: String = lua.exec_string(r"
+        local ok, err = pcall(function()
+            check_port(80)
+            check_port(80000)
+        end)
+        return err").unwrap();
+    assert_eq!(err, "[string \"...\"]:4: invalid port 80000");
+}
+
+#[test]
+fn load_from_reader() {
+    use std::io::{self, Cursor, Read};
+
+    let mut lua = Lua::new();
+
+    let script = format!("x = 0\n{}", "x = x + 1\n".repeat(2000));
+    lua.load_reader(Cursor::new(script.into_bytes()), "generated").unwrap();
+    let x: i32 = lua.query("x").unwrap();
+    assert_eq!(x, 2000);
+
+    match lua.load_reader(Cursor::new("x = = 1"), "broken") {
+        Err(td_rlua::LuaError::Syntax(msg)) => assert!(msg.contains("broken")),
+        _ => panic!(),
+    }
+
+    struct FailingReader {
+        sent: bool,
+    }
+    impl Read for FailingReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.sent {
+                return Err(io::Error::new(io::ErrorKind::Other, "disconnected"));
+            }
+            self.sent = true;
+            let chunk = b"y = 1\n";
+            buf[.. chunk.len()].copy_from_slice(chunk);
+            Ok(chunk.len())
+        }
+    }
+
+    match lua.load_reader(FailingReader { sent: false }, "remote") {
+        Err(td_rlua::LuaError::Io(err)) => assert_eq!(err.to_string(), "disconnected"),
+        _ => panic!(),
+    }
+    let y: Option<i32> = lua.query("y");
+    assert_eq!(y, None);
+}
+
+#[test]
+fn read_multiple_results() {
+    let mut lua = Lua::new();
+    let _: Option<()> = lua.exec_string("function stats() return 3, 'three', true end");
+
+    let top = unsafe { td_rlua::lua_gettop(lua.state()) };
+    let name = std::ffi::CString::new("stats").unwrap();
+    unsafe {
+        td_rlua::lua_getglobal(lua.state(), name.as_ptr());
+        td_rlua::lua_call(lua.state(), 0, 3);
+    }
+
+    let (count, word, flag): (i32, String, bool) = lua.read_results(3).unwrap();
+    assert_eq!(count, 3);
+    assert_eq!(word, "three");
+    assert!(flag);
+    assert_eq!(unsafe { td_rlua::lua_gettop(lua.state()) }, top);
+}
+
+#[test]
+fn utf8_helpers() {
+    let mut lua = Lua::new();
+    lua.open_utf8_helpers();
+
+    let len: i32 = lua.exec_string("return u8s.len('héllo wörld')").unwrap();
+    assert_eq!(len, 11);
+    let sub: String = lua.exec_string("return u8s.sub('héllo wörld', 2, 4)").unwrap();
+    assert_eq!(sub, "éll");
+    let sub: String = lua.exec_string("return u8s.sub('héllo wörld', -5)").unwrap();
+    assert_eq!(sub, "wörld");
+    let sub: String = lua.exec_string("return u8s.sub('héllo', 4, 2)").unwrap();
+    assert_eq!(sub, "");
+}
+
+#[test]
+fn location_prefix() {
+    let mut lua = Lua::new();
+
+    extern "C" fn check_port(lua: *mut td_rlua::lua_State) -> libc::c_int {
+        let port: i32 = td_rlua::LuaRead::lua_read_at_position(lua, 1).unwrap();
+        if port > 65535 {
+            let msg = format!("{}invalid port", Lua::from_existing_state(lua, false).location(1));
+            td_rlua::push_str_from(lua, &msg);
+            drop(msg);
+            unsafe { td_rlua::lua_error(lua) };
+        }
+        0
+    }
+    lua.register("check_port", check_port);
+
+    let script = "check_port(80)\ncheck_port(80000)\n";
+    match lua.load_reader(script.as_bytes(), "@config.lua") {
+        Err(td_rlua::LuaError::Runtime(msg)) => assert_eq!(msg, "config.lua:2: invalid port"),
+        _ => panic!(),
+    }
+}
+
+#[test]
+fn read_variadic_args() {
+    let mut lua = Lua::new();
+
+    extern "C" fn sum(lua: *mut td_rlua::lua_State) -> libc::c_int {
+        let values: Vec<i64> = Lua::from_existing_state(lua, false).read_args(1);
+        td_rlua::LuaPush::push_to_lua(values.iter().sum::<i64>(), lua)
+    }
+    lua.register("sum", sum);
+
+    let val: i64 = lua.exec_string("return sum(1, 2, 3, 4)").unwrap();
+    assert_eq!(val, 10);
+    let val: i64 = lua.exec_string("return sum()").unwrap();
+    assert_eq!(val, 0);
+    let val: i64 = lua.exec_string("return sum(5, 6, {}, 7)").unwrap();
+    assert_eq!(val, 11);
+}
+
+#[test]
+fn global_names() {
+    let mut lua = Lua::new();
+    assert!(!lua.global_names().contains(&"print".to_string()));
+
+    lua.openlibs();
+    lua.set("my_value", 3);
+    let _: Option<()> = lua.exec_string("_G[1] = true");
+
+    let names = lua.global_names();
+    assert!(names.contains(&"print".to_string()));
+    assert!(names.contains(&"type".to_string()));
+    assert!(names.contains(&"my_value".to_string()));
+    assert!(!names.contains(&"1".to_string()));
+}
+
+#[test]
+fn call_back_into_lua() {
+    let mut lua = Lua::new();
+    let _: Option<()> = lua.exec_string("function double(x) return x * 2 end");
+
+    extern "C" fn quadruple(lua: *mut td_rlua::lua_State) -> libc::c_int {
+        let mut lua = Lua::from_callback(lua);
+        assert!(!lua.is_owned());
+        // calls double with the whole stack, which holds our argument
+        let twice: i32 = lua.exec_func("double").unwrap();
+        lua.set("twice", twice);
+        let four_times: i32 = lua.exec_string("return double(twice)").unwrap();
+        td_rlua::LuaPush::push_to_lua(four_times, lua.state())
+    }
+    lua.register("quadruple", quadruple);
+
+    let val: i32 = lua.exec_string("return quadruple(3)").unwrap();
+    assert_eq!(val, 12);
+    let val: i32 = lua.exec_string("return quadruple(5)").unwrap();
+    assert_eq!(val, 20);
+}
+
+#[test]
+fn protected_scope() {
+    let mut lua = Lua::new();
+    lua.openlibs();
+
+    let sum = lua.protected(|lua| {
+        let a: i32 = lua.exec_string("return 1 + 2").unwrap();
+        a + 1
+    });
+    assert_eq!(sum.unwrap(), 4);
+
+    let top = unsafe { td_rlua::lua_gettop(lua.state()) };
+    let result = lua.protected(|lua| -> i32 {
+        unsafe {
+            // indexing nil raises an error outside of any pcall of the API
+            td_rlua::lua_pushnil(lua.state());
+            td_rlua::lua_getfield(lua.state(), -1, b"field\0".as_ptr() as *const _);
+        }
+        unreachable!()
+    });
+    match result {
+        Err(td_rlua::LuaError::Runtime(msg)) => assert!(msg.contains("attempt to index a nil value")),
+        _ => panic!(),
+    }
+    assert_eq!(unsafe { td_rlua::lua_gettop(lua.state()) }, top);
+
+    match lua.protected(|lua| lua.raise_error("stopped")) {
+        Err(td_rlua::LuaError::Runtime(msg)) => assert_eq!(msg, "stopped"),
+        _ => panic!(),
+    }
+}
+
+#[test]
+fn line_hook() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let lines = Rc::new(RefCell::new(Vec::new()));
+    let mut lua = Lua::new();
+    {
+        let lines = lines.clone();
+        lua.set_line_hook(move |source, line| lines.borrow_mut().push((source.to_string(), line)));
+    }
+
+    let script = "local a = 1\nlocal b = 2\n\nlocal c = a + b\n";
+    lua.load_reader(script.as_bytes(), "@script.lua").unwrap();
+
+    let lines: Vec<u32> = lines.borrow().iter().map(|&(ref source, line)| {
+        assert_eq!(source, "script.lua");
+        line
+    }).collect();
+    assert_eq!(lines, vec![1, 2, 4]);
+}
+
+#[test]
+fn closure_returning_several_values() {
+    let mut lua = Lua::new();
+    lua.openlibs();
+
+    lua.set("divmod", td_rlua::function2(|a: i32, b: i32| (a / b, a % b)));
+    lua.set("triple", td_rlua::function0(|| (1, "two", 3.5)));
+
+    let val: i32 = lua.exec_string("local q, r = divmod(17, 5); return q * 10 + r").unwrap();
+    assert_eq!(val, 32);
+    let val: i32 = lua.exec_string("return select('#', triple())").unwrap();
+    assert_eq!(val, 3);
+    let val: String = lua.exec_string("local a, b, c = triple(); return a .. b .. c").unwrap();
+    assert_eq!(val, "1two3.5");
+}
+
+#[test]
+fn positional_args() {
+    let mut lua = Lua::new();
+
+    // greet(name) or greet(greeting, name)
+    extern "C" fn greet(lua: *mut td_rlua::lua_State) -> libc::c_int {
+        let mut lua = Lua::from_callback(lua);
+        let msg = match lua.arg_count() {
+            1 => format!("hello {}", lua.arg::<String>(1).unwrap()),
+            2 => format!("{} {}", lua.arg::<String>(1).unwrap(), lua.arg::<String>(2).unwrap()),
+            n => format!("{} arguments", n),
+        };
+        assert!(lua.arg::<String>(0).is_none());
+        let past_end = lua.arg_count() + 1;
+        assert!(lua.arg::<String>(past_end).is_none());
+        td_rlua::LuaPush::push_to_lua(msg, lua.state())
+    }
+    lua.register("greet", greet);
+
+    let val: String = lua.exec_string("return greet('bob')").unwrap();
+    assert_eq!(val, "hello bob");
+    let val: String = lua.exec_string("return greet('hi', 'bob')").unwrap();
+    assert_eq!(val, "hi bob");
+    let val: String = lua.exec_string("return greet()").unwrap();
+    assert_eq!(val, "0 arguments");
+    let val: String = lua.exec_string("return greet(1, 2, 3)").unwrap();
+    assert_eq!(val, "3 arguments");
+}
+
+#[test]
+fn run_with_deadline() {
+    use std::time::{Duration, Instant};
+
+    let mut lua = Lua::new();
+
+    let start = Instant::now();
+    match lua.run_with_deadline("while true do end", start + Duration::from_millis(50)) {
+        Err(td_rlua::LuaError::Timeout) => (),
+        _ => panic!(),
+    }
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(50));
+    assert!(elapsed < Duration::from_secs(2));
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    lua.run_with_deadline("x = 0 for i = 1, 1000 do x = x + i end", deadline).unwrap();
+    let x: i32 = lua.query("x").unwrap();
+    assert_eq!(x, 500500);
+
+    match lua.run_with_deadline("error('oops')", deadline) {
+        Err(td_rlua::LuaError::Runtime(msg)) => assert!(msg.contains("oops")),
+        _ => panic!(),
+    }
+
+    // the hook is gone once done
+    let val: i32 = lua.exec_string("local n = 0 for i = 1, 100000 do n = n + 1 end return n").unwrap();
+    assert_eq!(val, 100000);
+}
+
+#[test]
+fn deadline_removed_on_panic() {
+    use std::panic::{self, AssertUnwindSafe};
+    use std::time::Instant;
+
+    let mut lua = Lua::new();
+    let state = lua.state();
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        td_rlua::hooks::with_deadline(state, Instant::now(), || panic!("gone"))
+    }));
+    assert!(result.is_err());
+
+    // the deadline has passed, but its hook is gone
+    let val: i32 = lua.exec_string("local n = 0 for i = 1, 100000 do n = n + 1 end return n").unwrap();
+    assert_eq!(val, 100000);
+}
+
+#[test]
+fn set_interrupt() {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    let mut lua = Lua::new();
+    let flag = Arc::new(AtomicBool::new(false));
+    lua.set_interrupt(flag.clone());
+
+    let setter = {
+        let flag = flag.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            flag.store(true, Ordering::Relaxed);
+        })
+    };
+    let mut results = lua.run_all(&["while true do end"]);
+    setter.join().unwrap();
+    match results.pop().unwrap() {
+        Err(td_rlua::LuaError::Runtime(msg)) => assert!(msg.ends_with("interrupted"), "{}", msg),
+        _ => panic!(),
+    }
+
+    flag.store(false, Ordering::Relaxed);
+    let val: i32 = lua.exec_string("local n = 0 for i = 1, 100000 do n = n + 1 end return n").unwrap();
+    assert_eq!(val, 100000);
+}
+
+#[test]
+fn arg_presence() {
+    use td_rlua::ArgPresence;
+
+    extern "C" fn presence(lua: *mut td_rlua::lua_State) -> libc::c_int {
+        let mut lua = Lua::from_callback(lua);
+        let name = match lua.arg_presence(2) {
+            ArgPresence::Absent => "absent",
+            ArgPresence::Nil => "nil",
+            ArgPresence::Present => "present",
+        };
+        td_rlua::LuaPush::push_to_lua(name, lua.state())
+    }
+
+    let mut lua = Lua::new();
+    lua.register("presence", presence);
+
+    let val: String = lua.exec_string("return presence(1)").unwrap();
+    assert_eq!(val, "absent");
+    let val: String = lua.exec_string("return presence(1, nil)").unwrap();
+    assert_eq!(val, "nil");
+    let val: String = lua.exec_string("return presence(1, false)").unwrap();
+    assert_eq!(val, "present");
+    let val: String = lua.exec_string("return presence(1, nil, 3)").unwrap();
+    assert_eq!(val, "nil");
+}
+
+#[test]
+fn register_fn_table() {
+    extern "C" fn add(lua: *mut td_rlua::lua_State) -> libc::c_int {
+        let mut lua = Lua::from_callback(lua);
+        let (a, b): (i32, i32) = (lua.arg(1).unwrap(), lua.arg(2).unwrap());
+        td_rlua::LuaPush::push_to_lua(a + b, lua.state())
+    }
+    extern "C" fn neg(lua: *mut td_rlua::lua_State) -> libc::c_int {
+        let mut lua = Lua::from_callback(lua);
+        let a: i32 = lua.arg(1).unwrap();
+        td_rlua::LuaPush::push_to_lua(-a, lua.state())
+    }
+    extern "C" fn name(lua: *mut td_rlua::lua_State) -> libc::c_int {
+        td_rlua::LuaPush::push_to_lua("math2", lua)
+    }
+
+    let mut lua = Lua::new();
+    let table = lua.register_fn_table(&[("add", add), ("neg", neg), ("name", name)]);
+    lua.copy_value(-1);
+    unsafe { td_rlua::lua_setglobal(lua.state(), b"math2\0".as_ptr() as *const libc::c_char) };
+    drop(table);
+    assert_eq!(lua.top(), 0);
+
+    let val: i32 = lua.exec_string("return math2.add(2, 3)").unwrap();
+    assert_eq!(val, 5);
+    let val: i32 = lua.exec_string("return math2.neg(7)").unwrap();
+    assert_eq!(val, -7);
+    let val: String = lua.exec_string("return math2.name()").unwrap();
+    assert_eq!(val, "math2");
+}
+
+#[test]
+fn check_arg() {
+    let mut lua = Lua::new();
+    lua.openlibs();
+
+    extern "C" fn rep(lua: *mut td_rlua::lua_State) -> libc::c_int {
+        let mut lua = Lua::from_callback(lua);
+        let count: usize = lua.check_arg(1, "rep");
+        let text: String = lua.check_arg(2, "rep");
+        td_rlua::LuaPush::push_to_lua(text.repeat(count), lua.state())
+    }
+    lua.register("rep", rep);
+
+    let val: String = lua.exec_string("return rep(3, 'ab')").unwrap();
+    assert_eq!(val, "ababab");
+
+    let err: String = lua.exec_string("local ok, err = pcall(rep, 'x', 'ab') return err").unwrap();
+    assert_eq!(err, "bad argument #1 to 'rep' (number expected, got string)");
+    let err: String = lua.exec_string("local ok, err = pcall(rep, 2, {}) return err").unwrap();
+    assert_eq!(err, "bad argument #2 to 'rep' (string expected, got table)");
+    let err: String = lua.exec_string("local ok, err = pcall(function() rep(2) end) return err").unwrap();
+    assert!(err.ends_with(":1: bad argument #2 to 'rep' (string expected, got no value)"));
+}
+
+#[test]
+fn unit_result() {
+    let mut lua = Lua::new();
+    lua.openlibs();
+
+    lua.set("check_port", td_rlua::function1(|port: i64| -> Result<(), String> {
+        if port > 0 && port < 65536 { Ok(()) } else { Err(format!("invalid port {}", port)) }
+    }));
+
+    let count: i32 = lua.exec_string("return select('#', check_port(80))").unwrap();
+    assert_eq!(count, 0);
+
+    let err: String = lua.exec_string("local ok, err = pcall(function()
+            check_port(80000)
+        end)
+        return err").unwrap();
+    assert_eq!(err, "[string \"local ok, err = pcall(function()...\"]:2: invalid port 80000");
+}
+
+#[test]
+fn register_global_function() {
+    let mut lua = Lua::new();
+
+    extern "C" fn double(lua: *mut td_rlua::lua_State) -> libc::c_int {
+        let mut lua = Lua::from_callback(lua);
+        let n: i64 = lua.check_arg(1, "double");
+        td_rlua::LuaPush::push_to_lua(n * 2, lua.state())
+    }
+    lua.register("double", double);
+
+    let val: i64 = lua.exec_string("local x = double(21) return x").unwrap();
+    assert_eq!(val, 42);
+    let f: td_rlua::LuaFunction = lua.query("double").unwrap();
+    assert_eq!(f.call::<_, i64>(4).unwrap(), 8);
+}
+
+#[test]
+fn opt_arg() {
+    let mut lua = Lua::new();
+    lua.openlibs();
+
+    extern "C" fn greet(lua: *mut td_rlua::lua_State) -> libc::c_int {
+        let mut lua = Lua::from_callback(lua);
+        let times: usize = lua.opt_arg(2, "greet", 1);
+        let name: String = lua.check_arg(1, "greet");
+        td_rlua::LuaPush::push_to_lua(format!("hi {}", name).repeat(times), lua.state())
+    }
+    lua.register("greet", greet);
+
+    let val: String = lua.exec_string("return greet('bo')").unwrap();
+    assert_eq!(val, "hi bo");
+    let val: String = lua.exec_string("return greet('bo', nil)").unwrap();
+    assert_eq!(val, "hi bo");
+    let val: String = lua.exec_string("return greet('bo', 2)").unwrap();
+    assert_eq!(val, "hi bohi bo");
+
+    let err: String = lua.exec_string("local ok, err = pcall(greet, 'bo', 'x') return err").unwrap();
+    assert_eq!(err, "bad argument #2 to 'greet' (number expected, got string)");
+}
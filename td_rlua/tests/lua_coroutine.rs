@@ -0,0 +1,94 @@
+extern crate td_rlua;
+extern crate libc;
+
+use td_rlua::{CoroutineStatus, Lua, LuaCoroutine, LuaError, LuaFunction};
+
+#[test]
+fn coroutine_status() {
+    let mut lua = Lua::new();
+    lua.openlibs();
+
+    let _: () = lua.exec_string("function counter(n) for i = 1, n do coroutine.yield(i) end return 'done' end").unwrap();
+    let counter: LuaFunction = lua.query("counter").unwrap();
+    let mut co = LuaCoroutine::new(&counter);
+    assert_eq!(co.status(), CoroutineStatus::Suspended);
+
+    assert_eq!(co.resume::<_, i32>(2).unwrap(), 1);
+    assert_eq!(co.status(), CoroutineStatus::Suspended);
+    assert_eq!(co.resume::<_, i32>(()).unwrap(), 2);
+    assert_eq!(co.resume::<_, String>(()).unwrap(), "done");
+    assert_eq!(co.status(), CoroutineStatus::Dead);
+
+    match co.resume::<_, ()>(()) {
+        Err(LuaError::Runtime(msg)) => assert_eq!(msg, "cannot resume dead coroutine"),
+        _ => panic!(),
+    }
+
+    let _: () = lua.exec_string("function fail() coroutine.yield() error('stop', 0) end").unwrap();
+    let fail: LuaFunction = lua.query("fail").unwrap();
+    let mut co = LuaCoroutine::new(&fail);
+    co.resume::<_, ()>(()).unwrap();
+    match co.resume::<_, ()>(()) {
+        Err(LuaError::Runtime(msg)) => assert_eq!(msg, "stop"),
+        _ => panic!(),
+    }
+    assert_eq!(co.status(), CoroutineStatus::Dead);
+}
+
+#[test]
+fn spawn_sandbox() {
+    let mut lua = Lua::new();
+    lua.openlibs();
+    let _: () = lua.exec_string("secret = 42 env_a = { yield = coroutine.yield, tostring = tostring } env_b = { tostring = tostring }").unwrap();
+
+    let env = lua.query("env_a").unwrap();
+    let mut a = lua.spawn_sandbox("x = 'a' yield(tostring(secret)) return tostring(y)", env).unwrap();
+    let env = lua.query("env_b").unwrap();
+    let mut b = lua.spawn_sandbox("y = 'b' return tostring(x)", env).unwrap();
+
+    let secret: String = a.resume(()).unwrap();
+    assert_eq!(secret, "nil");
+    let x: String = b.resume(()).unwrap();
+    assert_eq!(x, "nil");
+    let y: String = a.resume(()).unwrap();
+    assert_eq!(y, "nil");
+    assert_eq!(a.status(), CoroutineStatus::Dead);
+
+    let (x, y): (String, String) = (lua.exec_string("return env_a.x").unwrap(), lua.exec_string("return env_b.y").unwrap());
+    assert_eq!((x.as_str(), y.as_str()), ("a", "b"));
+    let leaked: bool = lua.exec_string("return x ~= nil or y ~= nil").unwrap();
+    assert!(!leaked);
+
+    let env = lua.query("env_b").unwrap();
+    match lua.spawn_sandbox("x = = 1", env) {
+        Err(LuaError::Syntax(msg)) => assert!(msg.starts_with("sandbox:1:")),
+        _ => panic!(),
+    }
+}
+
+#[test]
+fn coroutine_made_in_coroutine() {
+    use std::cell::RefCell;
+
+    thread_local!(static KEPT: RefCell<Option<LuaCoroutine>> = const { RefCell::new(None) });
+
+    extern "C" fn keep(lua: *mut td_rlua::lua_State) -> libc::c_int {
+        let mut lua = Lua::from_callback(lua);
+        let coroutine = lua.ref_function(1).map(|function| LuaCoroutine::new(&function));
+        KEPT.with(|kept| *kept.borrow_mut() = coroutine);
+        0
+    }
+
+    let mut lua = Lua::new();
+    lua.openlibs();
+    lua.register("keep", keep);
+
+    let _: () = lua.exec_string("coroutine.wrap(function() keep(function(x) coroutine.yield(x + 1) return x + 2 end) end)()").unwrap();
+    lua.exec_gc();
+    let _: () = lua.exec_string("junk = {} for i = 1, 1000 do junk[i] = string.rep('x', 150 + i % 100) .. i end").unwrap();
+
+    let mut coroutine = KEPT.with(|kept| kept.borrow_mut().take()).unwrap();
+    assert_eq!(coroutine.resume::<_, i32>(1).unwrap(), 2);
+    assert_eq!(coroutine.resume::<_, i32>(()).unwrap(), 3);
+    assert_eq!(coroutine.status(), CoroutineStatus::Dead);
+}
@@ -0,0 +1,150 @@
+extern crate td_rlua;
+extern crate libc;
+
+use td_rlua::{Lua, LuaError, LuaFunction};
+
+#[test]
+fn call_function() {
+    let mut lua = Lua::new();
+
+    let _: () = lua.exec_string("function add(a, b) return a + b end").unwrap();
+    let add: LuaFunction = lua.query("add").unwrap();
+    let top = lua.top();
+    assert_eq!(add.call::<_, i32>((2, 3)).unwrap(), 5);
+    assert_eq!(lua.top(), top);
+
+    match add.call::<_, String>((2, {})) {
+        Err(LuaError::Runtime(msg)) => assert!(msg.contains("attempt to perform arithmetic")),
+        _ => panic!(),
+    }
+    match add.call::<_, bool>((2, 3)) {
+        Err(LuaError::Runtime(msg)) => assert_eq!(msg, "bad result (boolean expected, got number)"),
+        _ => panic!(),
+    }
+    assert_eq!(lua.top(), top);
+
+    lua.set("sum", add.clone());
+    let val: i32 = lua.exec_string("return sum(10, 1)").unwrap();
+    assert_eq!(val, 11);
+}
+
+#[test]
+fn reload_chunk() {
+    let mut lua = Lua::new();
+
+    let mut chunk = lua.load_chunk("return 1", "my_plugin").unwrap();
+    let f = chunk.function();
+    assert_eq!(f.call::<_, i32>(()).unwrap(), 1);
+
+    chunk.reload("return 2").unwrap();
+    assert_eq!(f.call::<_, i32>(()).unwrap(), 2);
+
+    match chunk.reload("return = 3") {
+        Err(LuaError::Syntax(msg)) => assert!(msg.starts_with("my_plugin:1:")),
+        _ => panic!(),
+    }
+    assert_eq!(f.call::<_, i32>(()).unwrap(), 2);
+}
+
+#[test]
+fn stored_callback() {
+    use std::cell::RefCell;
+
+    thread_local! {
+        static CALLBACK: RefCell<Option<LuaFunction>> = const { RefCell::new(None) };
+    }
+
+    extern "C" fn on_event(lua: *mut td_rlua::lua_State) -> libc::c_int {
+        let mut lua = Lua::from_callback(lua);
+        let callback = lua.ref_function(1);
+        let stored = callback.is_some();
+        CALLBACK.with(|cb| *cb.borrow_mut() = callback);
+        td_rlua::LuaPush::push_to_lua(stored, lua.state())
+    }
+
+    let mut lua = Lua::new();
+    lua.register("on_event", on_event);
+
+    let stored: bool = lua.exec_string("return on_event(42)").unwrap();
+    assert!(!stored);
+    let stored: bool = lua.exec_string("local seen = 0
+        return on_event(function(n) seen = seen + n return seen end)").unwrap();
+    assert!(stored);
+
+    let callback = CALLBACK.with(|cb| cb.borrow_mut().take()).unwrap();
+    assert_eq!(callback.call::<_, i32>(5).unwrap(), 5);
+    assert_eq!(callback.call::<_, i32>(2).unwrap(), 7);
+}
+
+#[test]
+fn pcall_with_handler() {
+    let mut lua = Lua::new();
+    lua.openlibs();
+
+    let _: () = lua.exec_string("function handler(err) return '[plugin] ' .. err end
+                                 function div(a, b) if b == 0 then error('division by zero', 0) end return a // b end").unwrap();
+    let top = lua.top();
+
+    let handler: LuaFunction = lua.query("handler").unwrap();
+    let div: LuaFunction = lua.query("div").unwrap();
+
+    td_rlua::LuaPush::push_to_lua(div.clone(), lua.state());
+    td_rlua::LuaPush::push_to_lua((7, 2), lua.state());
+    lua.pcall_with_handler(2, 1, handler.clone()).unwrap();
+    assert_eq!(lua.top(), top + 1);
+    let quotient: i32 = lua.read_results(1).unwrap();
+    assert_eq!(quotient, 3);
+
+    td_rlua::LuaPush::push_to_lua(div, lua.state());
+    td_rlua::LuaPush::push_to_lua((7, 0), lua.state());
+    match lua.pcall_with_handler(2, 1, handler) {
+        Err(LuaError::Runtime(msg)) => assert_eq!(msg, "[plugin] division by zero"),
+        _ => panic!(),
+    }
+    assert_eq!(lua.top(), top);
+}
+
+#[test]
+fn load() {
+    let mut lua = Lua::new();
+    let top = lua.top();
+
+    lua.load("local a, b = ... return a * b").unwrap();
+    assert_eq!(lua.top(), top + 1);
+    let product: LuaFunction = lua.ref_function(-1).unwrap();
+    lua.pop(1);
+    assert_eq!(product.call::<_, i32>((6, 7)).unwrap(), 42);
+
+    match lua.load("x = = 1\nother line") {
+        Err(LuaError::Syntax(msg)) => assert!(msg.starts_with("chunk:1:"), "{}", msg),
+        _ => panic!(),
+    }
+    assert_eq!(lua.top(), top);
+}
+
+#[test]
+fn handle_taken_in_coroutine() {
+    use std::cell::RefCell;
+
+    thread_local!(static KEPT: RefCell<Option<LuaFunction>> = const { RefCell::new(None) });
+
+    extern "C" fn keep(lua: *mut td_rlua::lua_State) -> libc::c_int {
+        let mut lua = Lua::from_callback(lua);
+        let function = lua.ref_function(1);
+        KEPT.with(|kept| *kept.borrow_mut() = function);
+        0
+    }
+
+    let mut lua = Lua::new();
+    lua.openlibs();
+    lua.register("keep", keep);
+
+    let _: () = lua.exec_string("coroutine.wrap(function() keep(function(x) return x * 2 end) end)()").unwrap();
+    lua.exec_gc();
+    // reusing the memory of the collected thread
+    let _: () = lua.exec_string("junk = {} for i = 1, 1000 do junk[i] = string.rep('x', 150 + i % 100) .. i end").unwrap();
+
+    let function = KEPT.with(|kept| kept.borrow_mut().take()).unwrap();
+    assert_eq!(function.call::<_, i32>(21).unwrap(), 42);
+    drop(function);
+}
@@ -1,4 +1,6 @@
+#[macro_use]
 extern crate td_rlua;
+extern crate libc;
 
 use td_rlua::{Lua, LuaTable};
 
@@ -107,3 +109,400 @@ fn empty_array() {
     let mut table: LuaTable = lua.query("a").unwrap();
     assert!(3 == table.query("b").unwrap());
 }
+
+#[test]
+fn struct_from_table() {
+    #[derive(Debug, PartialEq)]
+    struct Config {
+        host: String,
+        port: u16,
+    }
+
+    impl_from_lua_table!(Config {
+        host,
+        #[lua(default)]
+        port,
+    });
+    impl_into_lua_table!(Config { host, port });
+
+    let mut lua = Lua::new();
+
+    let config: Config = lua.exec_string("return { host = 'localhost', port = 8080 }").unwrap();
+    assert_eq!(config, Config { host: "localhost".to_string(), port: 8080 });
+
+    let config: Config = lua.exec_string("return { host = 'localhost' }").unwrap();
+    assert_eq!(config.port, 0);
+
+    let config: Option<Config> = lua.exec_string("return { port = 8080 }");
+    assert!(config.is_none());
+
+    let config: Option<Config> = lua.exec_string("return { host = 'localhost', port = 'http' }");
+    assert!(config.is_none());
+
+    lua.set("c", Config { host: "example.com".to_string(), port: 443 });
+    let port: u16 = lua.exec_string("return c.port").unwrap();
+    assert_eq!(port, 443);
+    let host: String = lua.exec_string("return c.host").unwrap();
+    assert_eq!(host, "example.com");
+}
+
+#[test]
+fn extend_array() {
+    let mut lua = Lua::new();
+
+    let _:() = lua.exec_string("a = { 1, 2 }").unwrap();
+    {
+        let mut table : LuaTable = lua.query("a").unwrap();
+        table.extend_array(vec![3, 4, 5]);
+        assert_eq!(table.table_len(), 5);
+    }
+
+    let values: Vec<i32> = lua.query("a").unwrap();
+    assert_eq!(values, vec![1, 2, 3, 4, 5]);
+}
+
+#[test]
+fn query_or() {
+    let mut lua = Lua::new();
+
+    let _:() = lua.exec_string("config = { port = 8080, host = 'localhost' }").unwrap();
+    let mut table : LuaTable = lua.query("config").unwrap();
+    assert_eq!(table.query_or("port", 80), 8080);
+    assert_eq!(table.query_or("timeout", 30), 30);
+    assert_eq!(table.query_or("host", 5), 5);
+    assert_eq!(table.query_or("host", "none".to_string()), "localhost");
+}
+
+#[test]
+fn append() {
+    let mut lua = Lua::new();
+
+    let _:() = lua.exec_string("a = { 'x', 'y' }").unwrap();
+    {
+        let mut table : LuaTable = lua.query("a").unwrap();
+        table.append("z");
+        table.append(4);
+        assert_eq!(table.table_len(), 4);
+    }
+
+    let third : String = lua.exec_string("return a[3]").unwrap();
+    assert_eq!(third, "z");
+    let fourth : i32 = lua.exec_string("return a[4]").unwrap();
+    assert_eq!(fourth, 4);
+}
+
+#[test]
+fn struct_renamed_fields() {
+    #[derive(Debug, PartialEq)]
+    struct Monster {
+        name: String,
+        max_hp: i32,
+        attack_speed: f64,
+    }
+
+    impl_from_lua_table!(Monster {
+        name,
+        #[lua(rename = "maxHp")]
+        max_hp,
+        #[lua(default, rename = "attackSpeed")]
+        attack_speed,
+    });
+    impl_into_lua_table!(Monster { name, #[lua(rename = "maxHp")] max_hp, #[lua(rename = "attackSpeed")] attack_speed });
+
+    let mut lua = Lua::new();
+
+    let monster: Monster = lua.exec_string("return { name = 'orc', maxHp = 30 }").unwrap();
+    assert_eq!(monster, Monster { name: "orc".to_string(), max_hp: 30, attack_speed: 0.0 });
+
+    let monster: Option<Monster> = lua.exec_string("return { name = 'orc', max_hp = 30 }");
+    assert!(monster.is_none());
+
+    lua.set("m", Monster { name: "troll".to_string(), max_hp: 80, attack_speed: 1.5 });
+    let hp: i32 = lua.exec_string("return m.maxHp").unwrap();
+    assert_eq!(hp, 80);
+    let speed: f64 = lua.exec_string("return m.attackSpeed").unwrap();
+    assert_eq!(speed, 1.5);
+}
+
+#[test]
+fn for_each() {
+    let mut lua = Lua::new();
+
+    let _:() = lua.exec_string("a = { 1, 2, 3, x = 10, y = 'skip' }").unwrap();
+    let mut table : LuaTable = lua.query("a").unwrap();
+
+    let mut sum = 0;
+    table.for_each(|_: td_rlua::LuaValue, value: i32| sum += value);
+    assert_eq!(sum, 16);
+
+    let mut keys = vec![];
+    table.for_each(|key: String, _: td_rlua::LuaValue| keys.push(key));
+    keys.sort();
+    assert_eq!(keys, vec!["1", "2", "3", "x", "y"]);
+}
+
+#[test]
+fn for_each_restores_stack_on_panic() {
+    use std::panic::{self, AssertUnwindSafe};
+
+    let mut lua = Lua::new();
+
+    let _:() = lua.exec_string("a = { 1, 2, 3 }").unwrap();
+    let mut table : LuaTable = lua.query("a").unwrap();
+    let top = unsafe { td_rlua::lua_gettop(lua.state()) };
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        table.for_each(|_: i32, value: i32| if value == 2 { panic!("stop") });
+    }));
+    assert!(result.is_err());
+    assert_eq!(unsafe { td_rlua::lua_gettop(lua.state()) }, top);
+    assert_eq!(table.table_len(), 3);
+}
+
+#[test]
+fn defaulting_table() {
+    let mut lua = Lua::new();
+
+    {
+        let mut counts = lua.make_defaulting_table("counts", 0);
+        counts.set("apples", 3);
+    }
+    let _:() = lua.exec_string("counts.pears = counts.pears + 2").unwrap();
+
+    let apples: i32 = lua.exec_string("return counts.apples").unwrap();
+    assert_eq!(apples, 3);
+    let pears: i32 = lua.exec_string("return counts.pears").unwrap();
+    assert_eq!(pears, 2);
+    let missing: i32 = lua.exec_string("return counts[42]").unwrap();
+    assert_eq!(missing, 0);
+
+    {
+        let mut names = lua.make_defaulting_table("names", "unknown".to_string());
+        assert_eq!(names.query::<String, _>("anyone"), Some("unknown".to_string()));
+    }
+}
+
+#[test]
+fn ipairs() {
+    let mut lua = Lua::new();
+
+    let _:() = lua.exec_string("a = { 10, 20, nil, 40 }").unwrap();
+    let mut table : LuaTable = lua.query("a").unwrap();
+    let values: Vec<(usize, i32)> = table.ipairs().collect();
+    assert_eq!(values, vec![(1, 10), (2, 20)]);
+
+    let _:() = lua.exec_string("b = { 'x', 'y', {}, 'z' }").unwrap();
+    let mut table : LuaTable = lua.query("b").unwrap();
+    let values: Vec<(usize, String)> = table.ipairs().collect();
+    assert_eq!(values, vec![(1, "x".to_string()), (2, "y".to_string())]);
+}
+
+#[test]
+fn swap_remove() {
+    let mut lua = Lua::new();
+
+    let _:() = lua.exec_string("a = { 10, 20, 30, 40 }").unwrap();
+    {
+        let mut table : LuaTable = lua.query("a").unwrap();
+        assert!(table.swap_remove(2));
+        assert_eq!(table.table_len(), 3);
+        assert_eq!(table.query::<i32, _>(2), Some(40));
+        assert!(table.swap_remove(3));
+        assert!(!table.swap_remove(3));
+        assert!(!table.swap_remove(0));
+    }
+
+    let values: Vec<i32> = lua.query("a").unwrap();
+    assert_eq!(values, vec![10, 40]);
+}
+
+#[test]
+fn deep_clone() {
+    let mut lua = Lua::new();
+    lua.openlibs();
+
+    let _:() = lua.exec_string("config = { name = 'main', sub = { size = 2, list = { 1, 2 } }, f = print }
+                                config.me = config
+                                config.again = config.sub").unwrap();
+    {
+        let mut config : LuaTable = lua.query("config").unwrap();
+        let _copy = config.deep_clone();
+        unsafe {
+            td_rlua::lua_pushvalue(lua.state(), -1);
+            td_rlua::lua_setglobal(lua.state(), b"copy\0".as_ptr() as *const _);
+        }
+    }
+
+    let _:() = lua.exec_string("copy.sub.size = 5 copy.sub.list[1] = 9 copy.name = 'other'").unwrap();
+    let size: i32 = lua.exec_string("return config.sub.size").unwrap();
+    assert_eq!(size, 2);
+    let first: i32 = lua.exec_string("return config.sub.list[1]").unwrap();
+    assert_eq!(first, 1);
+    let name: String = lua.exec_string("return config.name").unwrap();
+    assert_eq!(name, "main");
+
+    let shape: bool = lua.exec_string("return copy.me == copy and copy.again == copy.sub and copy.sub ~= config.sub
+                                       and copy.f == config.f").unwrap();
+    assert!(shape);
+}
+
+#[test]
+fn new_table_sized() {
+    let mut lua = Lua::new();
+    let top = lua.top();
+
+    let sized : Vec<i32> = {
+        let mut table = lua.new_table_sized(10000, 1);
+        table.extend_array(0 .. 10000);
+        table.set("name", "sized");
+        assert_eq!(table.table_len(), 10000);
+        td_rlua::LuaRead::lua_read(lua.state()).unwrap()
+    };
+    assert_eq!(lua.top(), top);
+
+    let default : Vec<i32> = {
+        let mut table = lua.new_table_sized(0, 0);
+        table.extend_array(0 .. 10000);
+        td_rlua::LuaRead::lua_read(lua.state()).unwrap()
+    };
+    assert_eq!(sized, default);
+    assert_eq!(sized.len(), 10000);
+}
+
+#[test]
+fn partition_keys() {
+    let mut lua = Lua::new();
+
+    let _:() = lua.exec_string("a = { 'x', 'y', 'z', name = 'n', [10] = 1, [-3] = 2, [1.5] = 3, [true] = 4, kind = 'k' }").unwrap();
+    let mut table : LuaTable = lua.query("a").unwrap();
+    let (mut numbers, mut strings) = table.partition_keys();
+    numbers.sort();
+    strings.sort();
+    assert_eq!(numbers, vec![-3, 1, 2, 3, 10]);
+    assert_eq!(strings, vec!["kind".to_string(), "name".to_string()]);
+
+    let (numbers, strings) = table.partition_keys();
+    assert_eq!(numbers.len() + strings.len(), 7);
+}
+
+#[test]
+fn tagged_enum() {
+    #[derive(Debug, PartialEq)]
+    enum Shape {
+        Circle { radius: i32 },
+        Rect { width: i32, height: i32 },
+        Empty,
+    }
+
+    impl_from_lua_tagged!(Shape, tag = "type" {
+        "circle" => Circle { radius },
+        "rect" => Rect { #[lua(rename = "w")] width, #[lua(default)] height },
+        "empty" => Empty {},
+    });
+
+    let mut lua = Lua::new();
+
+    let shape: Shape = lua.exec_string("return { type = 'circle', radius = 5 }").unwrap();
+    assert_eq!(shape, Shape::Circle { radius: 5 });
+    let shape: Shape = lua.exec_string("return { type = 'rect', w = 3 }").unwrap();
+    assert_eq!(shape, Shape::Rect { width: 3, height: 0 });
+    let shape: Shape = lua.exec_string("return { type = 'empty' }").unwrap();
+    assert_eq!(shape, Shape::Empty);
+
+    let shape: Option<Shape> = lua.exec_string("return { type = 'square', radius = 5 }");
+    assert!(shape.is_none());
+    let shape: Option<Shape> = lua.exec_string("return { radius = 5 }");
+    assert!(shape.is_none());
+    let shape: Option<Shape> = lua.exec_string("return { type = 'circle', radius = 'big' }");
+    assert!(shape.is_none());
+}
+
+#[test]
+fn merge() {
+    extern "C" fn merge(lua: *mut td_rlua::lua_State) -> libc::c_int {
+        let mut lua = Lua::from_callback(lua);
+        let mut into: LuaTable = lua.arg(1).unwrap();
+        let mut from: LuaTable = lua.arg(2).unwrap();
+        let deep: bool = lua.arg(3).unwrap();
+        let top = lua.top();
+        into.merge(&mut from, deep);
+        assert_eq!(lua.top(), top);
+        0
+    }
+
+    let mut lua = Lua::new();
+    lua.register("merge", merge);
+
+    let code = "defaults = { volume = 5, name = 'player', video = { width = 800, height = 600 }, keys = { 'w', 'a' } }
+                user = { volume = 8, video = { width = 1920 }, keys = { 'z' }, extra = true }
+                merge(defaults, user, true)";
+    let _:() = lua.exec_string(code).unwrap();
+    let check = "return defaults.volume == 8 and defaults.name == 'player' and defaults.extra
+                        and defaults.video.width == 1920 and defaults.video.height == 600
+                        and defaults.video ~= user.video
+                        and defaults.keys[1] == 'z' and defaults.keys[2] == 'a'";
+    let merged: bool = lua.exec_string(check).unwrap();
+    assert!(merged);
+
+    let code = "shallow = { video = { width = 640, depth = 32 } }
+                merge(shallow, user, false)
+                return shallow.video == user.video and shallow.volume == 8";
+    let replaced: bool = lua.exec_string(code).unwrap();
+    assert!(replaced);
+
+    // cycles on both sides
+    let code = "a = { n = 1 } a.self = a  b = { m = 2 } b.self = b
+                merge(a, b, true)
+                return a.self == a and a.n == 1 and a.m == 2 and b.self == b and b.n == nil";
+    let cyclic: bool = lua.exec_string(code).unwrap();
+    assert!(cyclic);
+}
+
+#[test]
+fn enum_str() {
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    enum Direction {
+        North,
+        South,
+    }
+
+    impl_enum_str!(Direction {
+        North => "north",
+        South => "south",
+    });
+
+    let mut lua = Lua::new();
+
+    lua.set("d", Direction::North);
+    let text: String = lua.query("d").unwrap();
+    assert_eq!(text, "north");
+    assert_eq!(lua.query::<Direction, _>("d"), Some(Direction::North));
+
+    let dir: Direction = lua.exec_string("return 'south'").unwrap();
+    assert_eq!(dir, Direction::South);
+    let dir: Option<Direction> = lua.exec_string("return 'up'");
+    assert_eq!(dir, None);
+    let dir: Option<Direction> = lua.exec_string("return 1");
+    assert_eq!(dir, None);
+}
+
+#[test]
+fn get_path() {
+    let mut lua = Lua::new();
+
+    let _:() = lua.exec_string("config = { server = { port = 80, tls = { enabled = true, cert = 'a.pem' } }, name = 'x' }").unwrap();
+    let mut config : LuaTable = lua.query("config").unwrap();
+    assert_eq!(config.get_path::<bool>("server.tls.enabled"), Some(true));
+    assert_eq!(config.get_path::<i32>("server.port"), Some(80));
+    assert_eq!(config.get_path::<String>("name"), Some("x".to_string()));
+    assert_eq!(config.get_path::<bool>("server.tls.missing"), None);
+    assert_eq!(config.get_path::<bool>("server.port.enabled"), None);
+    assert_eq!(config.get_path::<bool>("name.len"), None);
+    assert_eq!(config.get_path::<bool>("nothing.at.all"), None);
+
+    {
+        let mut server : LuaTable = config.get_path("server").unwrap();
+        assert_eq!(server.get_path::<String>("tls.cert"), Some("a.pem".to_string()));
+    }
+    assert_eq!(config.get_path::<i32>("server.port"), Some(80));
+}
@@ -0,0 +1,107 @@
+extern crate td_rlua;
+
+use td_rlua::{Lua, LuaValue};
+
+#[test]
+fn read_scalars() {
+    let mut lua = Lua::new();
+
+    let val: LuaValue = lua.exec_string("return 3").unwrap();
+    assert_eq!(val, LuaValue::Integer(3));
+
+    let val: LuaValue = lua.exec_string("return 3.5").unwrap();
+    assert_eq!(val, LuaValue::Number(3.5));
+
+    let val: LuaValue = lua.exec_string("return 'hello'").unwrap();
+    assert_eq!(val, LuaValue::String("hello".to_string()));
+
+    let val: LuaValue = lua.exec_string("return true").unwrap();
+    assert_eq!(val, LuaValue::Boolean(true));
+}
+
+#[test]
+fn read_nested_table() {
+    let mut lua = Lua::new();
+
+    let val: LuaValue = lua.exec_string("return { 1, { 2 } }").unwrap();
+    assert_eq!(val, LuaValue::Table(vec![
+        (LuaValue::Integer(1), LuaValue::Integer(1)),
+        (LuaValue::Integer(2), LuaValue::Table(vec![(LuaValue::Integer(1), LuaValue::Integer(2))])),
+    ]));
+}
+
+#[test]
+fn read_self_referential_table() {
+    let mut lua = Lua::new();
+
+    let _: () = lua.exec_string("a = { 1 }; a[2] = a").unwrap();
+    let val: LuaValue = lua.query("a").unwrap();
+    assert_eq!(val, LuaValue::Table(vec![
+        (LuaValue::Integer(1), LuaValue::Integer(1)),
+        (LuaValue::Integer(2), LuaValue::Cycle),
+    ]));
+}
+
+#[test]
+fn roundtrip_nested_table() {
+    let mut lua = Lua::new();
+
+    let value = LuaValue::Table(vec![
+        (LuaValue::String("name".to_string()), LuaValue::String("orc".to_string())),
+        (LuaValue::String("stats".to_string()), LuaValue::Table(vec![
+            (LuaValue::Integer(1), LuaValue::Number(2.5)),
+            (LuaValue::String("alive".to_string()), LuaValue::Boolean(true)),
+        ])),
+    ]);
+    lua.set("v", value.clone());
+
+    let hp: f64 = lua.exec_string("return v.stats[1]").unwrap();
+    assert_eq!(hp, 2.5);
+
+    td_rlua::LuaPush::push_to_lua(LuaValue::Integer(7), lua.state());
+    assert_eq!(LuaValue::from_stack(lua.state(), -1), Some(LuaValue::Integer(7)));
+    unsafe { td_rlua::lua_pop(lua.state(), 1) };
+
+    let read: LuaValue = lua.query("v").unwrap();
+    let sorted = |v: LuaValue| match v {
+        LuaValue::Table(mut pairs) => {
+            pairs.sort_by(|a, b| format!("{:?}", a.0).cmp(&format!("{:?}", b.0)));
+            pairs.into_iter().map(|(k, v)| match v {
+                LuaValue::Table(mut inner) => {
+                    inner.sort_by(|a, b| format!("{:?}", a.0).cmp(&format!("{:?}", b.0)));
+                    (k, LuaValue::Table(inner))
+                }
+                v => (k, v),
+            }).collect::<Vec<_>>()
+        }
+        _ => panic!(),
+    };
+    assert_eq!(sorted(read), sorted(value));
+}
+
+#[test]
+fn max_table_depth() {
+    let mut lua = Lua::new();
+    lua.set_max_table_depth(50);
+
+    let mut value = LuaValue::Integer(1);
+    for _ in 0 .. 1000 {
+        value = LuaValue::Table(vec![(LuaValue::Integer(1), value)]);
+    }
+    assert_eq!(value.depth(), 1000);
+    let top = lua.top();
+    let err = value.clone().try_push(lua.state()).unwrap_err();
+    assert_eq!(err.message, "tables nested 1000 deep, the limit is 50");
+    assert_eq!(lua.top(), top);
+
+    let _: () = lua.exec_string("t = 1 for i = 1, 100 do t = { t } end").unwrap();
+    let top = lua.top();
+    assert!(lua.query::<LuaValue, _>("t").is_none());
+    assert_eq!(lua.top(), top);
+
+    lua.set_max_table_depth(2000);
+    assert!(lua.query::<LuaValue, _>("t").is_some());
+    assert_eq!(value.try_push(lua.state()), Ok(1));
+    let pushed: LuaValue = td_rlua::LuaRead::lua_read(lua.state()).unwrap();
+    assert_eq!(pushed.depth(), 1000);
+}
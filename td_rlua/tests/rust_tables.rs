@@ -1,7 +1,7 @@
 extern crate td_rlua;
 
 use td_rlua::{Lua, LuaTable};
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
 
 #[test]
 fn write() {
@@ -56,3 +56,87 @@ fn write_set() {
 
     assert_eq!(values, set);
 }
+
+#[test]
+fn readwrite_vec_deque() {
+    let mut lua = Lua::new();
+
+    let mut queue = VecDeque::new();
+    queue.push_back(2);
+    queue.push_back(3);
+    queue.push_front(1);
+
+    lua.set("a", queue.clone());
+
+    let first: i32 = lua.exec_string("return a[1]").unwrap();
+    assert_eq!(first, 1);
+
+    let values: VecDeque<i32> = lua.query("a").unwrap();
+    assert_eq!(values, queue);
+}
+
+#[test]
+fn push_lazy_iter() {
+    let mut lua = Lua::new();
+
+    lua.push_iter((1..1001).map(|i| i * 2));
+    unsafe { td_rlua::lua_setglobal(lua.state(), b"a\0".as_ptr() as *const _) };
+
+    let len: i32 = lua.exec_string("return #a").unwrap();
+    assert_eq!(len, 1000);
+    let values: Vec<i32> = lua.query("a").unwrap();
+    assert_eq!(values, (1..1001).map(|i| i * 2).collect::<Vec<_>>());
+}
+
+#[test]
+fn readwrite_range_inclusive() {
+    let mut lua = Lua::new();
+
+    lua.set("r", 1..=5i64);
+    let (from, to): (i64, i64) = (lua.exec_string("return r.from").unwrap(), lua.exec_string("return r.to").unwrap());
+    assert_eq!((from, to), (1, 5));
+    let inclusive: bool = lua.exec_string("return r.inclusive").unwrap();
+    assert!(inclusive);
+
+    let range: std::ops::RangeInclusive<i64> = lua.query("r").unwrap();
+    assert_eq!(range, 1..=5);
+
+    let _: () = lua.exec_string("h = { from = 1, to = 5 }").unwrap();
+    assert!(lua.query::<std::ops::RangeInclusive<i64>, _>("h").is_none());
+}
+
+#[test]
+fn read_pairs() {
+    let mut lua = Lua::new();
+
+    let _: () = lua.exec_string("a = { x = 1, y = 2, z = 'skip', 10, [2.5] = 3 }").unwrap();
+
+    let td_rlua::Pairs(mut pairs): td_rlua::Pairs<String, i32> = lua.query("a").unwrap();
+    pairs.sort();
+    assert_eq!(pairs, vec![("1".to_string(), 10), ("2.5".to_string(), 3),
+                           ("x".to_string(), 1), ("y".to_string(), 2)]);
+
+    let td_rlua::Pairs(pairs): td_rlua::Pairs<i32, i32> = lua.query("a").unwrap();
+    assert_eq!(pairs, vec![(1, 10)]);
+}
+
+#[test]
+fn readwrite_bound() {
+    use std::ops::Bound;
+
+    let mut lua = Lua::new();
+
+    for bound in vec![Bound::Included(3i64), Bound::Excluded(-4), Bound::Unbounded] {
+        lua.set("b", bound);
+        let read: Bound<i64> = lua.query("b").unwrap();
+        assert_eq!(read, bound);
+    }
+
+    let kind: String = lua.exec_string("return b.kind").unwrap();
+    assert_eq!(kind, "unbounded");
+
+    let _: () = lua.exec_string("c = { kind = 'included', value = 7 } d = { kind = 'included' } e = { kind = 'open' }").unwrap();
+    assert_eq!(lua.query::<Bound<i64>, _>("c"), Some(Bound::Included(7)));
+    assert!(lua.query::<Bound<i64>, _>("d").is_none());
+    assert!(lua.query::<Bound<i64>, _>("e").is_none());
+}
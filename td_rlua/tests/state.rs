@@ -0,0 +1,120 @@
+extern crate td_rlua;
+extern crate libc;
+
+use td_rlua::{Lua, LuaPush, LuaRead};
+
+#[test]
+fn into_raw_keeps_state_open() {
+    let mut lua = Lua::new();
+    assert!(lua.is_owned());
+    lua.set("a", 5);
+
+    let state = lua.into_raw();
+
+    let mut lua = Lua::from_existing_state(state, true);
+    assert!(lua.is_owned());
+    let a: i32 = lua.query("a").unwrap();
+    assert_eq!(a, 5);
+    lua.close();
+}
+
+#[test]
+fn borrowed_state_is_not_closed() {
+    let mut lua = Lua::new();
+    lua.set("a", 5);
+
+    {
+        let borrowed = Lua::from_existing_state(lua.state(), false);
+        assert!(!borrowed.is_owned());
+        borrowed.close();
+    }
+
+    let a: i32 = lua.query("a").unwrap();
+    assert_eq!(a, 5);
+}
+
+#[test]
+fn gc_is_running() {
+    let mut lua = Lua::new();
+    assert!(lua.gc_is_running());
+
+    lua.gc_stop();
+    assert!(!lua.gc_is_running());
+
+    lua.gc_restart();
+    assert!(lua.gc_is_running());
+}
+
+#[test]
+fn gc_collect_for() {
+    use std::time::{Duration, Instant};
+
+    let mut lua = Lua::new();
+    lua.openlibs();
+    lua.gc_stop();
+    let _: () = lua.exec_string("for i = 1, 200000 do local t = { i, tostring(i) } end").unwrap();
+    let before: f64 = lua.exec_string("return collectgarbage('count')").unwrap();
+
+    let start = Instant::now();
+    lua.gc_collect_for(Duration::from_millis(1));
+    assert!(start.elapsed() < Duration::from_millis(200));
+
+    assert!(lua.gc_collect_for(Duration::from_secs(10)));
+    let after: f64 = lua.exec_string("return collectgarbage('count')").unwrap();
+    assert!(after < before / 2.0, "{} {}", before, after);
+    assert!(!lua.gc_is_running());
+}
+
+#[test]
+fn registry_slots() {
+    let mut lua = Lua::new();
+    lua.openlibs();
+
+    extern "C" fn max_players(lua: *mut td_rlua::lua_State) -> libc::c_int {
+        let mut lua = Lua::from_callback(lua);
+        let max: i32 = lua.get_registry("game.max_players").unwrap_or(0);
+        td_rlua::LuaPush::push_to_lua(max, lua.state())
+    }
+    lua.register("max_players", max_players);
+
+    lua.set_registry("game.max_players", 16);
+    assert_eq!(lua.get_registry::<i32>("game.max_players"), Some(16));
+    assert_eq!(lua.get_registry::<i32>("game.missing"), None);
+
+    let val: i32 = lua.exec_string("return max_players()").unwrap();
+    assert_eq!(val, 16);
+    let hidden: bool = lua.exec_string("return _G['game.max_players'] == nil").unwrap();
+    assert!(hidden);
+}
+
+#[test]
+fn snapshot_globals() {
+    let mut lua = Lua::new();
+    lua.openlibs();
+    let _: () = lua.exec_string("config = { level = 1, tags = { 'a' } } count = 3").unwrap();
+
+    let top = lua.top();
+    "sentinel".push_to_lua(lua.state());
+    let snapshot = lua.snapshot_globals();
+    assert_eq!(lua.top(), top + 1);
+    let sentinel: String = LuaRead::lua_read(lua.state()).unwrap();
+    assert_eq!(sentinel, "sentinel");
+    lua.pop(1);
+
+    for _ in 0..2 {
+        let _: () = lua.exec_string("added = true count = 4 config.level = 2 table.insert(config.tags, 'b') print = nil").unwrap();
+        let top = lua.top();
+        lua.restore_globals(&snapshot);
+        assert_eq!(lua.top(), top);
+
+        let restored: bool = lua.exec_string("return added == nil and count == 3 and config.level == 1
+                                                     and #config.tags == 1 and _G == _ENV and print ~= nil").unwrap();
+        assert!(restored);
+        let text: String = lua.exec_string("return string.format('%d', count)").unwrap();
+        assert_eq!(text, "3");
+        let same: bool = lua.exec_string("return package.loaded.string == string and require('table') == table
+                                                 and ('x').upper == string.upper and string.shout == nil").unwrap();
+        assert!(same);
+        let _: () = lua.exec_string("string.shout = function(s) return s:upper() .. '!' end").unwrap();
+    }
+}
@@ -1,4 +1,5 @@
 extern crate td_rlua;
+extern crate libc;
 
 use td_rlua::lua_State;
 use td_rlua::Lua;
@@ -204,4 +205,791 @@ fn custom_struct() {
 
     let obj : Option<&mut TestLuaSturct> = lua.exec_string("return TestLuaSturct()");
     assert_eq!(obj.unwrap().index, 19);
-}
\ No newline at end of file
+}
+#[test]
+fn dispatch_methods() {
+    struct Counter {
+        value : i32,
+    }
+
+    impl NewStruct for Counter {
+        fn new() -> Counter {
+            Counter {
+                value : 0,
+            }
+        }
+
+        fn name() -> &'static str {
+            "Counter"
+        }
+    }
+
+    let mut lua = Lua::new();
+    lua.openlibs();
+
+    let mut value = td_rlua::LuaStruct::<Counter>::new(lua.state());
+    value.create().def("fixed", td_rlua::function0(|| 7)).register_dispatch(|obj, name, lua| {
+        match name {
+            "add" => {
+                let val : i32 = LuaRead::lua_read_at_position(lua, 2).unwrap();
+                obj.value += val;
+                Some(0)
+            }
+            "get" => Some(obj.value.push_to_lua(lua)),
+            _ => None,
+        }
+    });
+
+    let _ : Option<()> = lua.exec_string("x = Counter()");
+    let _ : Option<()> = lua.exec_string("x:add(3); x:add(4)");
+    let val : Option<i32> = lua.exec_string("return x:get()");
+    assert_eq!(val, Some(7));
+    let val : Option<i32> = lua.exec_string("return x.fixed()");
+    assert_eq!(val, Some(7));
+    let val : Option<i32> = lua.exec_string("return x:unknown()");
+    assert_eq!(val, None);
+}
+
+#[test]
+fn dispatch_panic() {
+    struct Gauge;
+
+    impl NewStruct for Gauge {
+        fn new() -> Gauge {
+            Gauge
+        }
+
+        fn name() -> &'static str {
+            "Gauge"
+        }
+    }
+
+    let mut lua = Lua::new();
+    lua.openlibs();
+    lua.set_panic_behavior(td_rlua::PanicBehavior::ConvertToError);
+
+    let mut value = td_rlua::LuaStruct::<Gauge>::new(lua.state());
+    value.create().register_dispatch(|_, name, _| -> Option<i32> {
+        panic!("gauge has no {}", name)
+    });
+
+    let err : String = lua.exec_string("local ok, err = pcall(function() return Gauge():read() end) return err").unwrap();
+    assert!(err.starts_with("gauge has no read"), "{}", err);
+}
+
+#[test]
+fn borrowed_str_field() {
+    struct Document {
+        text : String,
+    }
+
+    impl NewStruct for Document {
+        fn new() -> Document {
+            Document {
+                text : "abcdefgh".repeat(1024 * 16),
+            }
+        }
+
+        fn name() -> &'static str {
+            "Document"
+        }
+    }
+
+    extern "C" fn text(lua: *mut lua_State) -> libc::c_int {
+        let obj : &mut Document = td_rlua::read_userdata(lua, 1).unwrap();
+        td_rlua::push_str_from(lua, &obj.text)
+    }
+
+    let mut lua = Lua::new();
+    let mut value = td_rlua::LuaStruct::<Document>::new(lua.state());
+    value.create().register("text", text);
+
+    let _ : Option<()> = lua.exec_string("x = Document()");
+    let val : String = lua.exec_string("return x:text()").unwrap();
+    assert_eq!(val.len(), 8 * 1024 * 16);
+    assert_eq!(val, "abcdefgh".repeat(1024 * 16));
+}
+
+#[test]
+fn equal_with_metamethod() {
+    struct Point {
+        x : i32,
+    }
+    impl LuaPush for Point {
+        fn push_to_lua(self, lua: *mut lua_State) -> i32 {
+            td_rlua::userdata::push_userdata(self, lua, |mut table| {
+                table.set("__eq", td_rlua::function2(|a: &mut Point, b: &mut Point| a.x == b.x));
+            })
+        }
+    }
+    impl<'a> LuaRead for &'a mut Point {
+        fn lua_read_with_pop(lua: *mut lua_State, index: i32, _pop: i32) -> Option<&'a mut Point> {
+            td_rlua::userdata::read_userdata(lua, index)
+        }
+    }
+
+    let mut lua = Lua::new();
+
+    Point { x: 1 }.push_to_lua(lua.state());
+    Point { x: 1 }.push_to_lua(lua.state());
+    Point { x: 2 }.push_to_lua(lua.state());
+    assert!(lua.equal(-3, -2));
+    assert!(!lua.raw_equal(-3, -2));
+    assert!(!lua.equal(-3, -1));
+    assert!(lua.raw_equal(-1, -1));
+}
+
+#[test]
+fn forbid_newindex() {
+    struct Frozen {
+        value : i32,
+    }
+
+    impl NewStruct for Frozen {
+        fn new() -> Frozen {
+            Frozen {
+                value : 3,
+            }
+        }
+
+        fn name() -> &'static str {
+            "Frozen"
+        }
+    }
+
+    impl<'a> LuaRead for &'a mut Frozen {
+        fn lua_read_with_pop(lua: *mut lua_State, index: i32, _pop: i32) -> Option<&'a mut Frozen> {
+            td_rlua::userdata::read_userdata(lua, index)
+        }
+    }
+
+    let mut lua = Lua::new();
+    lua.openlibs();
+    let mut value = td_rlua::LuaStruct::<Frozen>::new(lua.state());
+    value.create().def("get", td_rlua::function1(|obj : &mut Frozen| obj.value)).forbid_newindex();
+
+    let _ : Option<()> = lua.exec_string("x = Frozen()");
+    let err : String = lua.exec_string("local ok, err = pcall(function() x.value = 5 end); return err").unwrap();
+    assert!(err.contains("cannot modify immutable object"));
+    let val : Option<i32> = lua.exec_string("return x:get()");
+    assert_eq!(val, Some(3));
+}
+
+#[test]
+fn non_send_userdata() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    struct Shared(Rc<RefCell<i32>>);
+
+    extern "C" fn add(lua: *mut lua_State) -> libc::c_int {
+        let obj : &mut Shared = td_rlua::read_userdata(lua, 1).unwrap();
+        let val : i32 = LuaRead::lua_read_at_position(lua, 2).unwrap();
+        *obj.0.borrow_mut() += val;
+        0
+    }
+
+    impl LuaPush for Shared {
+        fn push_to_lua(self, lua: *mut lua_State) -> i32 {
+            td_rlua::push_userdata(self, lua, |mut table| {
+                table.empty_table("__index").register("add", add);
+            })
+        }
+    }
+
+    let value = Rc::new(RefCell::new(1));
+    let mut lua = Lua::new();
+    lua.set("shared", Shared(value.clone()));
+    assert_eq!(Rc::strong_count(&value), 2);
+
+    let _ : Option<()> = lua.exec_string("shared:add(4); shared:add(5)");
+    assert_eq!(*value.borrow(), 10);
+
+    drop(lua);
+    assert_eq!(Rc::strong_count(&value), 1);
+}
+
+#[test]
+fn weak_userdata() {
+    use std::sync::{Arc, Mutex};
+
+    struct Entity {
+        hp : i32,
+    }
+
+    extern "C" fn hit(lua: *mut lua_State) -> libc::c_int {
+        let entity = td_rlua::read_weak_userdata::<Entity>(lua, 1);
+        let mut entity = entity.lock().unwrap();
+        entity.hp -= 1;
+        entity.hp.push_to_lua(lua)
+    }
+
+    let entity = Arc::new(Mutex::new(Entity { hp : 10 }));
+    let mut lua = Lua::new();
+    lua.openlibs();
+    td_rlua::push_weak_userdata(Arc::downgrade(&entity), lua.state(), |mut table| {
+        table.empty_table("__index").register("hit", hit);
+    });
+    unsafe { td_rlua::lua_setglobal(lua.state(), b"entity\0".as_ptr() as *const libc::c_char) };
+
+    let hp : Option<i32> = lua.exec_string("return entity:hit()");
+    assert_eq!(hp, Some(9));
+    assert_eq!(entity.lock().unwrap().hp, 9);
+
+    drop(entity);
+    let err : String = lua.exec_string("local ok, err = pcall(entity.hit, entity); return err").unwrap();
+    assert_eq!(err, "object destroyed");
+}
+
+#[test]
+fn register_method() {
+    struct Accumulator {
+        total : i32,
+    }
+
+    impl NewStruct for Accumulator {
+        fn new() -> Accumulator {
+            Accumulator {
+                total : 0,
+            }
+        }
+
+        fn name() -> &'static str {
+            "Accumulator"
+        }
+    }
+
+    impl Accumulator {
+        fn add(&mut self, x : i32) -> i32 {
+            self.total += x;
+            self.total
+        }
+
+        fn scale(&mut self, (num, den) : (i32, i32)) {
+            self.total = self.total * num / den;
+        }
+
+        fn total(&mut self, _ : ()) -> i32 {
+            self.total
+        }
+    }
+
+    let mut lua = Lua::new();
+    lua.openlibs();
+    let mut value = td_rlua::LuaStruct::<Accumulator>::new(lua.state());
+    value.create()
+         .register_method("add", Accumulator::add)
+         .register_method("scale", Accumulator::scale)
+         .register_method("total", Accumulator::total);
+
+    let _ : Option<()> = lua.exec_string("x = Accumulator()");
+    let val : Option<i32> = lua.exec_string("return x:add(5)");
+    assert_eq!(val, Some(5));
+    let val : Option<i32> = lua.exec_string("x:add(7); x:scale(3, 4); return x:total()");
+    assert_eq!(val, Some(9));
+
+    let err : String = lua.exec_string("local ok, err = pcall(x.add, x, 'five'); return err").unwrap();
+    assert_eq!(err, "wrong parameter types for method 'add'");
+    let err : String = lua.exec_string("local ok, err = pcall(x.add, 1, 2); return err").unwrap();
+    assert_eq!(err, "bad self for method 'add'");
+}
+
+#[test]
+fn register_method_ref() {
+    struct Queue {
+        items : Vec<i32>,
+    }
+
+    impl NewStruct for Queue {
+        fn new() -> Queue {
+            Queue {
+                items : vec![1, 2, 3],
+            }
+        }
+
+        fn name() -> &'static str {
+            "Queue"
+        }
+    }
+
+    impl Queue {
+        fn len(&self, _ : ()) -> usize {
+            self.items.len()
+        }
+
+        fn get(&self, index : usize) -> i32 {
+            self.items[index]
+        }
+
+        fn push(&mut self, x : i32) {
+            self.items.push(x);
+        }
+    }
+
+    let mut lua = Lua::new();
+    lua.openlibs();
+    let mut value = td_rlua::LuaStruct::<Queue>::new(lua.state());
+    value.create()
+         .register_method_ref("len", Queue::len)
+         .register_method_ref("get", Queue::get)
+         .register_method("push", Queue::push);
+
+    let _ : Option<()> = lua.exec_string("q = Queue()");
+    let val : Option<usize> = lua.exec_string("return q:len()");
+    assert_eq!(val, Some(3));
+    let val : Option<i32> = lua.exec_string("q:push(4); return q:get(3)");
+    assert_eq!(val, Some(4));
+    let val : Option<usize> = lua.exec_string("return q:len()");
+    assert_eq!(val, Some(4));
+
+    let err : String = lua.exec_string("local ok, err = pcall(q.len, {}); return err").unwrap();
+    assert_eq!(err, "bad self for method 'len'");
+}
+
+#[test]
+fn instance_fields() {
+    struct Npc {
+        level : i32,
+    }
+
+    impl NewStruct for Npc {
+        fn new() -> Npc {
+            Npc {
+                level : 1,
+            }
+        }
+
+        fn name() -> &'static str {
+            "Npc"
+        }
+    }
+
+    impl Npc {
+        fn level(&mut self, _ : ()) -> i32 {
+            self.level
+        }
+    }
+
+    let mut lua = Lua::new();
+    let mut value = td_rlua::LuaStruct::<Npc>::new(lua.state());
+    value.create().register_method("level", Npc::level).enable_instance_fields();
+    value.def("kind", "npc");
+
+    let _ : Option<()> = lua.exec_string("a = Npc(); b = Npc()");
+    let _ : Option<()> = lua.exec_string("a.custom = 1; a.name = 'guard'");
+    let val : Option<i32> = lua.exec_string("return a.custom");
+    assert_eq!(val, Some(1));
+    let val : Option<String> = lua.exec_string("return a.name");
+    assert_eq!(val, Some("guard".to_string()));
+    let val : Option<bool> = lua.exec_string("return b.custom == nil");
+    assert_eq!(val, Some(true));
+
+    let val : Option<i32> = lua.exec_string("return a:level()");
+    assert_eq!(val, Some(1));
+    let val : Option<String> = lua.exec_string("return b.kind");
+    assert_eq!(val, Some("npc".to_string()));
+}
+
+#[test]
+fn protect_metatable() {
+    struct Vault {
+        gold : i32,
+    }
+
+    impl NewStruct for Vault {
+        fn new() -> Vault {
+            Vault {
+                gold : 100,
+            }
+        }
+
+        fn name() -> &'static str {
+            "Vault"
+        }
+    }
+
+    impl Vault {
+        fn gold(&mut self, _ : ()) -> i32 {
+            self.gold
+        }
+    }
+
+    let mut lua = Lua::new();
+    lua.openlibs();
+    let mut value = td_rlua::LuaStruct::<Vault>::new(lua.state());
+    value.create().register_method("gold", Vault::gold).protect_metatable();
+
+    let _ : Option<()> = lua.exec_string("v = Vault()");
+    let meta : Option<String> = lua.exec_string("return getmetatable(v)");
+    assert_eq!(meta, Some("protected".to_string()));
+    let _ : Option<()> = lua.exec_string("local m = getmetatable(v); pcall(function() m.__index.gold = nil end)");
+    let gold : Option<i32> = lua.exec_string("return v:gold()");
+    assert_eq!(gold, Some(100));
+}
+
+#[test]
+fn register_clone() {
+    #[derive(Clone)]
+    struct Inventory {
+        items : Vec<String>,
+    }
+
+    impl NewStruct for Inventory {
+        fn new() -> Inventory {
+            Inventory {
+                items : vec!["sword".to_string()],
+            }
+        }
+
+        fn name() -> &'static str {
+            "Inventory"
+        }
+    }
+
+    impl Inventory {
+        fn add(&mut self, item : String) {
+            self.items.push(item);
+        }
+
+        fn count(&mut self, _ : ()) -> usize {
+            self.items.len()
+        }
+    }
+
+    let mut lua = Lua::new();
+    lua.openlibs();
+    let mut value = td_rlua::LuaStruct::<Inventory>::new(lua.state());
+    value.create()
+         .register_method("add", Inventory::add)
+         .register_method("count", Inventory::count)
+         .register_clone();
+
+    let _ : Option<()> = lua.exec_string("a = Inventory(); a:add('shield'); b = a:clone(); b:add('bow'); b:add('axe')");
+    let count : Option<i32> = lua.exec_string("return a:count()");
+    assert_eq!(count, Some(2));
+    let count : Option<i32> = lua.exec_string("return b:count()");
+    assert_eq!(count, Some(4));
+    let same : Option<bool> = lua.exec_string("return getmetatable(a) == getmetatable(b)");
+    assert_eq!(same, Some(true));
+}
+
+#[test]
+fn batch_methods() {
+    struct Light {
+        on : bool,
+        level : i32,
+    }
+
+    impl NewStruct for Light {
+        fn new() -> Light {
+            Light { on : false, level : 3 }
+        }
+
+        fn name() -> &'static str {
+            "Light"
+        }
+    }
+
+    extern "C" fn toggle(lua: *mut lua_State) -> libc::c_int {
+        let light : &mut Light = td_rlua::read_userdata(lua, 1).unwrap();
+        light.on = !light.on;
+        0
+    }
+
+    extern "C" fn is_on(lua: *mut lua_State) -> libc::c_int {
+        let light : &mut Light = td_rlua::read_userdata(lua, 1).unwrap();
+        light.on.push_to_lua(lua)
+    }
+
+    extern "C" fn dim(lua: *mut lua_State) -> libc::c_int {
+        let light : &mut Light = td_rlua::read_userdata(lua, 1).unwrap();
+        light.level -= 1;
+        light.level.push_to_lua(lua)
+    }
+
+    let mut lua = Lua::new();
+    let mut value = td_rlua::LuaStruct::<Light>::new(lua.state());
+    value.create().methods(&[("toggle", toggle), ("is_on", is_on), ("dim", dim)]);
+
+    let _ : () = lua.exec_string("light = Light() light:toggle()").unwrap();
+    let on : bool = lua.exec_string("return light:is_on()").unwrap();
+    assert!(on);
+    let level : i32 = lua.exec_string("return light:dim()").unwrap();
+    assert_eq!(level, 2);
+}
+
+#[test]
+fn shared_metatable() {
+    struct Token;
+
+    impl LuaPush for Token {
+        fn push_to_lua(self, lua: *mut lua_State) -> i32 {
+            td_rlua::push_userdata_cached(self, lua, |mut table| {
+                table.set("__name", "Token");
+            })
+        }
+    }
+
+    let mut lua = Lua::new();
+    lua.openlibs();
+    lua.set("a", Token);
+    lua.set("b", Token);
+
+    let same : bool = lua.exec_string("return getmetatable(a) == getmetatable(b)").unwrap();
+    assert!(same);
+    let name : String = lua.exec_string("return getmetatable(b).__name").unwrap();
+    assert_eq!(name, "Token");
+}
+
+#[test]
+fn metatable_per_push() {
+    #[derive(Clone)]
+    struct Token;
+
+    impl LuaPush for Token {
+        fn push_to_lua(self, lua: *mut lua_State) -> i32 {
+            td_rlua::push_userdata(self, lua, |mut table| {
+                table.set("__name", "Token");
+            })
+        }
+    }
+
+    let mut lua = Lua::new();
+    lua.openlibs();
+    // set_default pushes the Token itself with an empty metatable
+    let mut table = lua.empty_table("defaults");
+    table.set_default(Token);
+    drop(table);
+    lua.set("a", Token);
+
+    let name : String = lua.exec_string("return getmetatable(a).__name").unwrap();
+    assert_eq!(name, "Token");
+    let name : String = lua.exec_string("return getmetatable(defaults.missing).__name").unwrap();
+    assert_eq!(name, "Token");
+}
+
+#[test]
+fn register_field() {
+    struct Point {
+        x : i32,
+        y : i32,
+    }
+
+    impl NewStruct for Point {
+        fn new() -> Point {
+            Point { x : 1, y : 2 }
+        }
+
+        fn name() -> &'static str {
+            "Point"
+        }
+    }
+
+    impl Point {
+        fn sum(&mut self, _: ()) -> i32 {
+            self.x + self.y
+        }
+    }
+
+    let mut lua = Lua::new();
+    lua.openlibs();
+    let mut value = td_rlua::LuaStruct::<Point>::new(lua.state());
+    value.create()
+         .register_method("sum", Point::sum)
+         .register_field("x", |p: &Point| p.x, |p: &mut Point, x: i32| p.x = x)
+         .register_field("y", |p: &Point| p.y, |p: &mut Point, y: i32| p.y = y);
+
+    let _ : () = lua.exec_string("p = Point()").unwrap();
+    let (x, y) : (i32, i32) = (lua.exec_string("return p.x").unwrap(), lua.exec_string("return p.y").unwrap());
+    assert_eq!((x, y), (1, 2));
+
+    let _ : () = lua.exec_string("p.x = 10 p.y = p.y * 3").unwrap();
+    let sum : i32 = lua.exec_string("return p:sum()").unwrap();
+    assert_eq!(sum, 16);
+
+    let err : String = lua.exec_string("local ok, err = pcall(function() p.x = 'a' end) return err").unwrap();
+    assert_eq!(err, "wrong value type for field 'x'");
+    let err : String = lua.exec_string("local ok, err = pcall(function() p.z = 1 end) return err").unwrap();
+    assert_eq!(err, "no field 'z' to set");
+    let missing : bool = lua.exec_string("return p.z == nil").unwrap();
+    assert!(missing);
+}
+
+#[test]
+fn register_str_field() {
+    use std::borrow::Cow;
+
+    struct Player {
+        name : String,
+        title : Option<String>,
+    }
+
+    impl NewStruct for Player {
+        fn new() -> Player {
+            Player { name : "ann".to_string(), title : None }
+        }
+
+        fn name() -> &'static str {
+            "Player"
+        }
+    }
+
+    impl Player {
+        fn display_name(&self) -> Cow<str> {
+            match self.title {
+                Some(ref title) => Cow::Owned(format!("{} {}", title, self.name)),
+                None => Cow::Borrowed(&self.name),
+            }
+        }
+    }
+
+    let mut lua = Lua::new();
+    lua.openlibs();
+    let mut value = td_rlua::LuaStruct::<Player>::new(lua.state());
+    value.create()
+         .register_str_field("name", |p: &Player| Cow::Borrowed(&p.name), |p: &mut Player, name: String| p.name = name)
+         .register_str_field("display", Player::display_name, |p: &mut Player, title: String| p.title = Some(title))
+         .register_field("level", |_: &Player| 1, |_: &mut Player, _: i32| {});
+
+    let _ : () = lua.exec_string("p = Player()").unwrap();
+    let name : String = lua.exec_string("return p.name").unwrap();
+    assert_eq!(name, "ann");
+    let display : String = lua.exec_string("return p.display").unwrap();
+    assert_eq!(display, "ann");
+
+    let display : String = lua.exec_string("p.name = 'bob' p.display = 'sir' return p.display").unwrap();
+    assert_eq!(display, "sir bob");
+    let level : i32 = lua.exec_string("return p.level").unwrap();
+    assert_eq!(level, 1);
+}
+
+#[test]
+fn register_eq_by() {
+    #[derive(PartialEq)]
+    struct Color {
+        rgb : u32,
+    }
+
+    impl NewStruct for Color {
+        fn new() -> Color {
+            Color { rgb : 0xff0000 }
+        }
+
+        fn name() -> &'static str {
+            "Color"
+        }
+    }
+
+    impl Color {
+        fn set(&mut self, rgb : u32) {
+            self.rgb = rgb;
+        }
+    }
+
+    struct Other;
+
+    impl LuaPush for Other {
+        fn push_to_lua(self, lua: *mut lua_State) -> i32 {
+            td_rlua::push_userdata(self, lua, |_| {})
+        }
+    }
+
+    let mut lua = Lua::new();
+    let mut value = td_rlua::LuaStruct::<Color>::new(lua.state());
+    value.create().register_method("set", Color::set).register_eq_by();
+    lua.set("other", Other);
+
+    let _ : () = lua.exec_string("a = Color() b = Color()").unwrap();
+    let equal : bool = lua.exec_string("return a == b").unwrap();
+    assert!(equal);
+
+    let equal : bool = lua.exec_string("b:set(255) return a == b").unwrap();
+    assert!(!equal);
+    let equal : bool = lua.exec_string("return a == other").unwrap();
+    assert!(!equal);
+}
+
+#[test]
+fn register_tostring_debug() {
+    #[derive(Debug)]
+    struct Point {
+        x : i32,
+        y : i32,
+    }
+
+    impl NewStruct for Point {
+        fn new() -> Point {
+            Point { x : 1, y : -2 }
+        }
+
+        fn name() -> &'static str {
+            "Point"
+        }
+    }
+
+    let mut lua = Lua::new();
+    lua.openlibs();
+    let mut value = td_rlua::LuaStruct::<Point>::new(lua.state());
+    value.create().register_tostring_debug();
+
+    let _ : () = lua.exec_string("output = {} print = function(v) output[#output + 1] = tostring(v) end").unwrap();
+    let _ : () = lua.exec_string("print(Point())").unwrap();
+    let output : String = lua.exec_string("return output[1]").unwrap();
+    assert_eq!(output, "Point { x: 1, y: -2 }");
+}
+
+#[test]
+fn register_gc_shared() {
+    use std::sync::{Arc, Mutex};
+
+    struct Counter {
+        n : i32,
+    }
+
+    impl NewStruct for Counter {
+        fn new() -> Counter {
+            Counter { n : 0 }
+        }
+
+        fn name() -> &'static str {
+            "Counter"
+        }
+    }
+
+    extern "C" fn incr(lua: *mut lua_State) -> libc::c_int {
+        match td_rlua::read_userdata::<Arc<Mutex<Counter>>>(lua, 1) {
+            Some(counter) => {
+                let mut counter = counter.lock().unwrap();
+                counter.n += 1;
+                counter.n.push_to_lua(lua)
+            }
+            None => 0,
+        }
+    }
+
+    struct Shared(Arc<Mutex<Counter>>);
+
+    impl LuaPush for Shared {
+        fn push_to_lua(self, lua: *mut lua_State) -> i32 {
+            td_rlua::push_shared_userdata(self.0, lua)
+        }
+    }
+
+    let mut lua = Lua::new();
+    let mut value = td_rlua::LuaStruct::<Counter>::new(lua.state());
+    value.register_gc_shared().register("incr", incr);
+
+    let shared = Arc::new(Mutex::new(Counter { n : 5 }));
+    lua.set("c", Shared(shared.clone()));
+    assert_eq!(Arc::strong_count(&shared), 2);
+
+    let n : i32 = lua.exec_string("return c:incr()").unwrap();
+    assert_eq!(n, 6);
+    let n : i32 = lua.exec_string("local fresh = Counter() return fresh:incr()").unwrap();
+    assert_eq!(n, 1);
+
+    let _ : () = lua.exec_string("c = nil").unwrap();
+    lua.exec_gc();
+    assert_eq!(Arc::strong_count(&shared), 1);
+    assert_eq!(shared.lock().unwrap().n, 6);
+}